@@ -0,0 +1,198 @@
+//! Packet parsing throughput on a synthetic capture
+//!
+//! `read fields` compares the parser's reader against the bit at a time reader it replaced,
+//! both read the fields of the same capture.
+//!
+//! Compare against another revision with `cargo bench -- --save-baseline main` there and
+//! `cargo bench -- --baseline main` here.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tracedecode::bench::Reader;
+use tracedecode::{parse_packets, DecoderConfig, PacketParser};
+
+/// Encode a packet from `(value, width)` fields following the header byte
+fn packet(fields: &[(u32, usize)]) -> Vec<u8> {
+    let bits: usize = 8 + fields.iter().map(|(_, width)| width).sum::<usize>();
+    let len = bits.div_ceil(8);
+    let mut res = vec![0u8; len];
+    res[0] = len as u8;
+
+    let mut pos = 8;
+    for (value, width) in fields {
+        for i in 0..*width {
+            if (value >> i) & 1 != 0 {
+                res[pos / 8] |= 1 << (pos % 8);
+            }
+            pos += 1;
+        }
+    }
+    res
+}
+
+/// A sync packet followed by a mix of branch map, address and exception packets
+fn capture(packets: u32) -> Vec<u8> {
+    let mut data = packet(&[
+        (0, 16),
+        (0b11, 2),
+        (0, 2),
+        (0, 1),
+        (0, 1),
+        (0x100 >> 1, 31),
+        (0, 3),
+    ]);
+
+    for index in 1..packets {
+        let index = index & 0xffff;
+        data.extend(match index % 4 {
+            0 => packet(&[(index, 16), (0b01, 2), (0, 5), (index, 31), (0, 2)]),
+            1 => packet(&[
+                (index, 16),
+                (0b01, 2),
+                (5, 5),
+                (0b10110, 7),
+                (0x4200_0000 >> 1, 31),
+                (0, 1),
+                (0, 1),
+                (0, 1),
+            ]),
+            2 => packet(&[(index, 16), (0b10, 2), (0x4200_1234 >> 1, 31), (0, 7)]),
+            _ => packet(&[
+                (index, 16),
+                (0b11, 2),
+                (1, 2),
+                (0, 1),
+                (0, 1),
+                (7, 5),
+                (1, 1),
+                (0x4000_0000 >> 1, 31),
+                (0x4200_0010, 32),
+                (0, 6),
+            ]),
+        });
+    }
+
+    data
+}
+
+/// Reads the next `bits` bits (up to 32) of a packet
+trait BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self;
+    fn get_bits(&mut self, bits: usize) -> u32;
+}
+
+/// The reader the parser used before reading fields a word at a time, kept as baseline
+struct BitAtATime<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> for BitAtATime<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn get_bits(&mut self, bits: usize) -> u32 {
+        let mut res = 0;
+        for i in 0..bits {
+            let bit = (self.data[self.position / 8] >> (self.position % 8)) & 1;
+            res |= (bit as u32) << i;
+            self.position += 1;
+        }
+        res
+    }
+}
+
+impl<'a> BitReader<'a> for Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader::new(data, 0)
+    }
+
+    fn get_bits(&mut self, bits: usize) -> u32 {
+        Reader::get_bits(self, bits).unwrap()
+    }
+}
+
+/// Read the same fields as the parser with the default configuration, returns a checksum of
+/// the fields
+fn read_fields<'a, R: BitReader<'a>>(data: &'a [u8]) -> u32 {
+    let mut checksum = 0;
+    let mut offset = 0;
+    while offset < data.len() {
+        let len = (data[offset] & 0b11111) as usize;
+        let mut reader = R::new(&data[offset..][..len]);
+        reader.get_bits(8);
+        checksum ^= reader.get_bits(16);
+
+        // the number of bits read so far and the widths of the remaining fields
+        let (mut position, fields): (usize, &[usize]) = match reader.get_bits(2) {
+            0b01 => match reader.get_bits(5) {
+                0 => (31, &[31]),
+                1 => (31, &[1, 31, 1, 1]),
+                2..=3 => (31, &[3, 31, 1, 1]),
+                4..=7 => (31, &[7, 31, 1, 1]),
+                8..=15 => (31, &[15, 31, 1, 1]),
+                _ => (31, &[31, 31, 1, 1]),
+            },
+            0b10 => (26, &[31, 1, 1]),
+            0b11 => match reader.get_bits(2) {
+                0 => (28, &[1, 1, 31]),
+                1 => (28, &[1, 1, 5, 1, 31, 32]),
+                _ => (28, &[1, 2]),
+            },
+            _ => (26, &[]),
+        };
+        for bits in fields {
+            checksum ^= reader.get_bits(*bits);
+            position += bits;
+        }
+        // sign extension
+        while position < len * 8 {
+            let bits = (len * 8 - position).min(32);
+            checksum ^= reader.get_bits(bits);
+            position += bits;
+        }
+
+        offset += len;
+    }
+    checksum
+}
+
+fn parse(c: &mut Criterion) {
+    let data = capture(100_000);
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("whole capture", |b| {
+        b.iter(|| parse_packets(&data, &DecoderConfig::default()).unwrap())
+    });
+    group.bench_function("4k chunks", |b| {
+        b.iter(|| {
+            let mut parser = PacketParser::new();
+            let mut packets = 0;
+            for chunk in data.chunks(4096) {
+                parser.push(chunk);
+                while parser.next_packet().unwrap().is_some() {
+                    packets += 1;
+                }
+            }
+            packets
+        })
+    });
+    group.finish();
+
+    // the reader alone, against the bit at a time reader the parser used before
+    assert_eq!(
+        read_fields::<BitAtATime>(&data),
+        read_fields::<Reader>(&data)
+    );
+    let mut group = c.benchmark_group("read fields");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("bit at a time", |b| {
+        b.iter(|| read_fields::<BitAtATime>(&data))
+    });
+    group.bench_function("parser reader", |b| b.iter(|| read_fields::<Reader>(&data)));
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,160 @@
+/// Outcomes of the inferable branches which weren't reached yet, oldest first
+///
+/// A bit-packed ring buffer, branches are pushed from format 1 packets at the back and consumed
+/// from the front. A set bit means the branch was taken.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BranchMap {
+    words: Vec<u64>,
+    /// Bit position of the oldest branch
+    head: usize,
+    len: usize,
+}
+
+impl BranchMap {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Add the branches of a format 1 packet, the first branch is bit 0 of `branch_map` and a
+    /// set bit means the branch was not taken
+    pub(crate) fn push_packet(&mut self, branches: u32, branch_map: u32) {
+        for i in 0..branches {
+            self.push_back((branch_map >> i) & 1 == 0);
+        }
+    }
+
+    pub(crate) fn push_back(&mut self, taken: bool) {
+        self.reserve();
+        let bit = (self.head + self.len) % self.capacity();
+        self.set(bit, taken);
+        self.len += 1;
+    }
+
+    /// Add a branch which comes before all others, e.g. the one a sync packet points to
+    pub(crate) fn push_front(&mut self, taken: bool) {
+        self.reserve();
+        self.head = (self.head + self.capacity() - 1) % self.capacity();
+        self.set(self.head, taken);
+        self.len += 1;
+    }
+
+    /// Take the outcome of the next branch
+    pub(crate) fn pop(&mut self) -> Option<bool> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let taken = self.get(self.head);
+        self.head = (self.head + 1) % self.capacity();
+        self.len -= 1;
+        Some(taken)
+    }
+
+    fn capacity(&self) -> usize {
+        self.words.len() * 64
+    }
+
+    fn get(&self, bit: usize) -> bool {
+        self.words[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    fn set(&mut self, bit: usize, value: bool) {
+        if value {
+            self.words[bit / 64] |= 1 << (bit % 64);
+        } else {
+            self.words[bit / 64] &= !(1 << (bit % 64));
+        }
+    }
+
+    /// Make room for one more branch, the branches are moved to the start when growing
+    fn reserve(&mut self) {
+        if self.len < self.capacity() {
+            return;
+        }
+
+        let mut grown = Self {
+            words: vec![0; (self.words.len() * 2).max(1)],
+            head: 0,
+            len: 0,
+        };
+        while let Some(taken) = self.pop() {
+            grown.push_back(taken);
+        }
+        *self = grown;
+    }
+}
+
+#[cfg(test)]
+fn drain(map: &mut BranchMap) -> Vec<bool> {
+    std::iter::from_fn(|| map.pop()).collect()
+}
+
+#[test]
+fn test_push_packet_order() {
+    let mut map = BranchMap::new();
+    // taken, taken, not taken
+    map.push_packet(3, 0b100);
+    // not taken, taken
+    map.push_packet(2, 0b01);
+
+    assert_eq!(map.len(), 5);
+    assert_eq!(drain(&mut map), [true, true, false, false, true]);
+    assert!(map.is_empty());
+    assert_eq!(map.pop(), None);
+}
+
+#[test]
+fn test_push_front() {
+    let mut map = BranchMap::new();
+    map.push_packet(2, 0b10);
+    map.push_front(false);
+
+    assert_eq!(drain(&mut map), [false, true, false]);
+
+    // the front of an empty map is also its back
+    map.push_front(true);
+    map.push_back(false);
+    assert_eq!(drain(&mut map), [true, false]);
+}
+
+#[test]
+fn test_wrap_around_and_grow() {
+    let mut map = BranchMap::new();
+    let mut expected = std::collections::VecDeque::new();
+
+    for i in 0..1000u32 {
+        let bits = i.wrapping_mul(0x9e37_79b9);
+        map.push_packet(i % 32, bits);
+        for b in 0..i % 32 {
+            expected.push_back((bits >> b) & 1 == 0);
+        }
+        if i % 3 == 0 {
+            map.push_front(i % 2 == 0);
+            expected.push_front(i % 2 == 0);
+        }
+        // consume most of it to make the buffer wrap around
+        for _ in 0..(i % 40) {
+            assert_eq!(map.pop(), expected.pop_front());
+        }
+        assert_eq!(map.len(), expected.len());
+    }
+
+    assert_eq!(drain(&mut map), Vec::from(expected));
+
+    map.push_back(true);
+    map.clear();
+    assert!(map.is_empty());
+}
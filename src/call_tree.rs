@@ -0,0 +1,463 @@
+use std::collections::BTreeMap;
+
+use object::File;
+
+use crate::code_map::CodeMap;
+use crate::event::TraceEvent;
+use crate::inst_decoder::{is_call, is_return, is_trap_return};
+use crate::json;
+use crate::symbols::Symbolizer;
+use crate::trap::Trap;
+
+/// A single invocation of a function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    /// The first traced PC of the invocation, usually the function's entry point
+    pub address: u32,
+    /// The PC of the instruction which called this function, if traced, for a trap handler the
+    /// PC of the trapping or interrupted instruction
+    pub call_site: Option<u32>,
+    /// Index of the first instruction of the invocation in the execution path
+    pub start: usize,
+    /// Number of instructions executed in this invocation, excluding callees
+    pub instructions: usize,
+    pub children: Vec<Call>,
+}
+
+impl Call {
+    fn new(address: u32, call_site: Option<u32>, start: usize) -> Self {
+        Self {
+            address,
+            call_site,
+            start,
+            instructions: 0,
+            children: Vec::new(),
+        }
+    }
+
+    /// Number of instructions executed in this invocation, including callees
+    pub fn total_instructions(&self) -> usize {
+        self.instructions
+            + self
+                .children
+                .iter()
+                .map(|child| child.total_instructions())
+                .sum::<usize>()
+    }
+}
+
+/// Calls reconstructed from an execution path
+///
+/// The trace usually starts somewhere down the call stack, so returning from the
+/// function the trace started in adds a new root above it. Trap handlers are entered like a
+/// call from the trapping instruction and left with `mret`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallTree {
+    /// One root per part of the trace without lost data, the calls can't be followed across a
+    /// gap
+    pub roots: Vec<Call>,
+}
+
+impl CallTree {
+    /// Build the call tree from the decoded events
+    pub fn new(events: &[TraceEvent], obj_files: &[File<'_>]) -> Self {
+        let code = CodeMap::new(obj_files);
+        let mut tracker = CallStackTracker::new();
+        // an instruction is tracked once it's known where execution continued
+        let mut previous: Option<(u32, &[u8])> = None;
+
+        for event in events {
+            match *event {
+                TraceEvent::Instruction { pc, .. } => {
+                    let insn = code.instruction(pc);
+                    if insn.is_empty() {
+                        continue;
+                    }
+                    if let Some((previous_pc, previous_insn)) = previous.replace((pc, insn)) {
+                        tracker.step(previous_pc, previous_insn, Some(pc));
+                    }
+                }
+                TraceEvent::Trap(trap) => {
+                    if let Some((previous_pc, previous_insn)) = previous.take() {
+                        tracker.step(previous_pc, previous_insn, None);
+                    }
+                    tracker.trap(trap);
+                }
+                // a resync on its own doesn't interrupt execution, after lost data it follows one
+                // of these
+                TraceEvent::Gap { .. }
+                | TraceEvent::TraceLost
+                | TraceEvent::TraceStop
+                | TraceEvent::TraceStart => {
+                    if let Some((previous_pc, previous_insn)) = previous.take() {
+                        tracker.step(previous_pc, previous_insn, None);
+                    }
+                    tracker.discontinuity();
+                }
+                _ => (),
+            }
+        }
+        if let Some((pc, insn)) = previous {
+            tracker.step(pc, insn, None);
+        }
+
+        tracker.finish()
+    }
+
+    /// Chrome Trace Event Format JSON for Perfetto or `chrome://tracing`
+    ///
+    /// Every call becomes a complete event, the timestamps are instruction indices in the
+    /// execution path and are shown as microseconds.
+    pub fn to_chrome_trace(&self, symbolizer: &Symbolizer) -> String {
+        let mut events = Vec::new();
+        for root in &self.roots {
+            add_trace_events(root, symbolizer, &mut events);
+        }
+
+        format!("{{\"traceEvents\":[\n{}\n]}}\n", events.join(",\n"))
+    }
+
+    /// Folded stacks like `main;foo;bar 123` for flamegraph tools, weighted by the number of
+    /// executed instructions
+    pub fn to_folded_stacks(&self, symbolizer: &Symbolizer) -> String {
+        let mut stacks = BTreeMap::new();
+        for root in &self.roots {
+            add_folded_stacks(root, symbolizer, &mut Vec::new(), &mut stacks);
+        }
+
+        stacks
+            .into_iter()
+            .map(|(stack, instructions)| format!("{} {}\n", stack, instructions))
+            .collect()
+    }
+}
+
+fn add_folded_stacks(
+    call: &Call,
+    symbolizer: &Symbolizer,
+    frames: &mut Vec<String>,
+    stacks: &mut BTreeMap<String, usize>,
+) {
+    let location = symbolizer.symbolize(call.address);
+    // `;` separates the frames, it shows up in demangled names like `<[u8; 4]>::foo`
+    let name = match location.function {
+        Some(function) => function.replace(';', ":"),
+        None => format!("{:#010x}", call.address),
+    };
+
+    frames.push(name);
+    if call.instructions != 0 {
+        *stacks.entry(frames.join(";")).or_default() += call.instructions;
+    }
+    for child in &call.children {
+        add_folded_stacks(child, symbolizer, frames, stacks);
+    }
+    frames.pop();
+}
+
+fn add_trace_events(call: &Call, symbolizer: &Symbolizer, events: &mut Vec<String>) {
+    let total_instructions = call.total_instructions();
+    if total_instructions == 0 {
+        return;
+    }
+
+    let location = symbolizer.symbolize(call.address);
+    let name = match location.function {
+        Some(function) if location.offset == 0 => function,
+        Some(function) => format!("{}+{:#x}", function, location.offset),
+        None => format!("{:#010x}", call.address),
+    };
+
+    let call_site = match call.call_site {
+        Some(call_site) => format!(",\"call_site\":\"{:#010x}\"", call_site),
+        None => String::new(),
+    };
+
+    events.push(format!(
+        "{{\"name\":{},\"cat\":\"call\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":0,\"tid\":0,\
+         \"args\":{{\"address\":\"{:#010x}\"{},\"self_instructions\":{}}}}}",
+        json::string(&name),
+        call.start,
+        total_instructions,
+        call.address,
+        call_site,
+        call.instructions
+    ));
+
+    for child in &call.children {
+        add_trace_events(child, symbolizer, events);
+    }
+}
+
+struct CallStackTracker {
+    stack: Vec<Call>,
+    /// Calls closed by a discontinuity
+    roots: Vec<Call>,
+    /// Number of instructions seen so far
+    position: usize,
+}
+
+impl CallStackTracker {
+    fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+            roots: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn step(&mut self, pc: u32, insn: &[u8], next_pc: Option<u32>) {
+        if self.stack.is_empty() {
+            self.stack.push(Call::new(pc, None, self.position));
+        }
+
+        self.stack.last_mut().unwrap().instructions += 1;
+        self.position += 1;
+
+        let next_pc = match next_pc {
+            Some(next_pc) => next_pc,
+            None => return,
+        };
+
+        if is_call(insn) {
+            self.stack.push(Call::new(next_pc, Some(pc), self.position));
+        } else if is_return(insn) || is_trap_return(insn) {
+            let call = self.stack.pop().unwrap();
+            match self.stack.last_mut() {
+                Some(caller) => caller.children.push(call),
+                None => {
+                    // returned from the function the trace started in, its caller was
+                    // already running when the trace started
+                    let mut caller = Call::new(next_pc, None, call.start);
+                    caller.children.push(call);
+                    self.stack.push(caller);
+                }
+            }
+        }
+    }
+
+    /// The handler is entered like a function called by the trapping instruction
+    fn trap(&mut self, trap: Trap) {
+        self.stack
+            .push(Call::new(trap.handler, Some(trap.epc), self.position));
+    }
+
+    /// Trace data got lost, close all open calls and start over with the next instruction
+    fn discontinuity(&mut self) {
+        while self.stack.len() > 1 {
+            let call = self.stack.pop().unwrap();
+            self.stack.last_mut().unwrap().children.push(call);
+        }
+        self.roots.extend(self.stack.pop());
+    }
+
+    fn finish(mut self) -> CallTree {
+        self.discontinuity();
+
+        CallTree { roots: self.roots }
+    }
+}
+
+#[cfg(test)]
+fn track(steps: &[(u32, &[u8])]) -> CallTree {
+    let mut tracker = CallStackTracker::new();
+    for (i, (pc, insn)) in steps.iter().enumerate() {
+        tracker.step(*pc, insn, steps.get(i + 1).map(|(pc, _)| *pc));
+    }
+    tracker.finish()
+}
+
+#[cfg(test)]
+const NOP: &[u8] = &[0x01, 0x00];
+#[cfg(test)]
+const CALL: &[u8] = &[0xef, 0x00, 0xc0, 0x16];
+#[cfg(test)]
+const RET: &[u8] = &[0x82, 0x80];
+#[cfg(test)]
+const MRET: &[u8] = &[0x73, 0x00, 0x20, 0x30];
+
+#[test]
+fn test_nested_calls() {
+    let tree = track(&[
+        (0x100, NOP),
+        (0x102, CALL),
+        (0x200, NOP),
+        (0x202, CALL),
+        (0x300, RET),
+        (0x206, RET),
+        (0x106, NOP),
+        (0x108, CALL),
+        (0x300, RET),
+        (0x10c, NOP),
+    ]);
+
+    assert_eq!(tree.roots[0].address, 0x100);
+    assert_eq!(tree.roots[0].start, 0);
+    assert_eq!(tree.roots[0].instructions, 5);
+    assert_eq!(tree.roots[0].total_instructions(), 10);
+    assert_eq!(tree.roots[0].children.len(), 2);
+
+    let first = &tree.roots[0].children[0];
+    assert_eq!(first.address, 0x200);
+    assert_eq!(first.start, 2);
+    assert_eq!(first.call_site, Some(0x102));
+    assert_eq!(first.instructions, 3);
+    assert_eq!(first.total_instructions(), 4);
+    assert_eq!(first.children[0].address, 0x300);
+
+    let second = &tree.roots[0].children[1];
+    assert_eq!(second.address, 0x300);
+    assert_eq!(second.start, 8);
+    assert_eq!(second.call_site, Some(0x108));
+    assert_eq!(second.instructions, 1);
+}
+
+#[test]
+fn test_return_from_initial_function() {
+    let tree = track(&[(0x200, NOP), (0x202, RET), (0x106, NOP), (0x108, NOP)]);
+
+    assert_eq!(tree.roots[0].address, 0x106);
+    assert_eq!(tree.roots[0].start, 0);
+    assert_eq!(tree.roots[0].instructions, 2);
+    assert_eq!(tree.roots[0].children.len(), 1);
+    assert_eq!(tree.roots[0].children[0].address, 0x200);
+    assert_eq!(tree.roots[0].children[0].instructions, 2);
+}
+
+#[test]
+fn test_unfinished_calls() {
+    let tree = track(&[(0x100, CALL), (0x200, CALL), (0x300, NOP)]);
+
+    assert_eq!(tree.roots[0].total_instructions(), 3);
+    assert_eq!(tree.roots[0].children[0].children[0].address, 0x300);
+}
+
+#[test]
+fn test_chrome_trace() {
+    let elf = crate::test_elf(
+        &[0u8; 0x400],
+        &[("main", 0x100, 0x10), ("foo", 0x200, 0x10)],
+    );
+    let symbolizer = Symbolizer::from_obj_files(&[object::File::parse(&*elf).unwrap()]);
+
+    let tree = track(&[
+        (0x100, NOP),
+        (0x102, CALL),
+        (0x200, NOP),
+        (0x202, CALL),
+        (0x300, RET),
+        (0x206, RET),
+        (0x106, NOP),
+    ]);
+
+    assert_eq!(
+        tree.to_chrome_trace(&symbolizer),
+        "{\"traceEvents\":[\n\
+         {\"name\":\"main\",\"cat\":\"call\",\"ph\":\"X\",\"ts\":0,\"dur\":7,\"pid\":0,\"tid\":0,\
+         \"args\":{\"address\":\"0x00000100\",\"self_instructions\":3}},\n\
+         {\"name\":\"foo\",\"cat\":\"call\",\"ph\":\"X\",\"ts\":2,\"dur\":4,\"pid\":0,\"tid\":0,\
+         \"args\":{\"address\":\"0x00000200\",\"call_site\":\"0x00000102\",\"self_instructions\":3}},\n\
+         {\"name\":\"0x00000300\",\"cat\":\"call\",\"ph\":\"X\",\"ts\":4,\"dur\":1,\"pid\":0,\"tid\":0,\
+         \"args\":{\"address\":\"0x00000300\",\"call_site\":\"0x00000202\",\"self_instructions\":1}}\n\
+         ]}\n"
+    );
+}
+
+#[test]
+fn test_folded_stacks() {
+    let elf = crate::test_elf(
+        &[0u8; 0x400],
+        &[("main", 0x100, 0x10), ("foo", 0x200, 0x10)],
+    );
+    let symbolizer = Symbolizer::from_obj_files(&[object::File::parse(&*elf).unwrap()]);
+
+    let tree = track(&[
+        (0x100, NOP),
+        (0x102, CALL),
+        (0x200, RET),
+        (0x106, CALL),
+        (0x200, NOP),
+        (0x202, CALL),
+        (0x300, RET),
+        (0x206, RET),
+        (0x10a, NOP),
+    ]);
+
+    assert_eq!(
+        tree.to_folded_stacks(&symbolizer),
+        "main 4\nmain;foo 4\nmain;foo;0x00000300 1\n"
+    );
+}
+
+#[test]
+fn test_trap_handler() {
+    let mut text = vec![0u8; 0x400];
+    for pc in [0x100, 0x102, 0x104, 0x106, 0x280] {
+        text[pc..pc + 2].copy_from_slice(NOP);
+    }
+    text[0x282..0x286].copy_from_slice(CALL);
+    text[0x286..0x28a].copy_from_slice(MRET);
+    text[0x300..0x302].copy_from_slice(RET);
+    let elf = crate::test_elf(&text, &[]);
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    // an interrupt at 0x104, the handler calls a function before returning
+    let mut events = crate::event::instructions(&[0x100, 0x102]);
+    events.push(TraceEvent::Trap(Trap {
+        cause: 7,
+        interrupt: true,
+        epc: 0x104,
+        handler: 0x280,
+    }));
+    events.extend(crate::event::instructions(&[
+        0x280, 0x282, 0x300, 0x286, 0x104, 0x106,
+    ]));
+    let tree = CallTree::new(&events, &obj_files);
+
+    assert_eq!(tree.roots[0].address, 0x100);
+    assert_eq!(tree.roots[0].instructions, 4);
+    assert_eq!(tree.roots[0].total_instructions(), 8);
+    assert_eq!(tree.roots[0].children.len(), 1);
+
+    let handler = &tree.roots[0].children[0];
+    assert_eq!(handler.address, 0x280);
+    assert_eq!(handler.call_site, Some(0x104));
+    assert_eq!(handler.start, 2);
+    assert_eq!(handler.instructions, 3);
+    assert_eq!(handler.children[0].address, 0x300);
+    assert_eq!(handler.children[0].instructions, 1);
+}
+
+#[test]
+fn test_gap_after_return() {
+    let mut text = vec![0u8; 0x400];
+    for pc in [0x100, 0x200, 0x300, 0x302] {
+        text[pc..pc + 2].copy_from_slice(NOP);
+    }
+    text[0x102..0x106].copy_from_slice(CALL);
+    text[0x202..0x204].copy_from_slice(RET);
+    let elf = crate::test_elf(&text, &[]);
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    // where the return went is lost, tracing continues somewhere else
+    let mut events = crate::event::instructions(&[0x100, 0x102, 0x200, 0x202]);
+    events.push(TraceEvent::Gap {
+        expected: 3,
+        index: 5,
+    });
+    events.push(TraceEvent::Resync { pc: 0x300 });
+    events.extend(crate::event::instructions(&[0x300, 0x302]));
+    let tree = CallTree::new(&events, &obj_files);
+
+    assert_eq!(tree.roots.len(), 2);
+    assert_eq!(tree.roots[0].address, 0x100);
+    assert_eq!(tree.roots[0].instructions, 2);
+    assert_eq!(tree.roots[0].children.len(), 1);
+    assert_eq!(tree.roots[0].children[0].address, 0x200);
+    assert_eq!(tree.roots[0].children[0].instructions, 2);
+
+    assert_eq!(tree.roots[1].address, 0x300);
+    assert_eq!(tree.roots[1].start, 4);
+    assert_eq!(tree.roots[1].instructions, 2);
+    assert!(tree.roots[1].children.is_empty());
+}
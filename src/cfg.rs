@@ -0,0 +1,457 @@
+use std::fmt::Write;
+
+use object::{File, Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+
+use crate::inst_decoder::{
+    estimate_next_inferable_pc, is_call, is_inferable_branch, is_inferable_jump, is_return,
+    is_uninferable_branch,
+};
+
+/// How control gets from one basic block to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// The block doesn't end with a jump, execution continues with the next block
+    Fallthrough,
+    /// A conditional branch was taken
+    Taken,
+    /// A conditional branch wasn't taken
+    NotTaken,
+    /// An unconditional direct jump
+    Jump,
+    /// A call, the edge leads to the instruction following it where the callee returns to
+    Call,
+    /// An indirect jump within the function, only known from the trace
+    Indirect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    /// Start of the block the edge leaves
+    pub from: u32,
+    /// Start of the block the edge leads to
+    pub to: u32,
+    pub kind: EdgeKind,
+    /// How often the edge was followed in the trace
+    pub count: u64,
+}
+
+/// A straight sequence of instructions which is only entered at its start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub address: u32,
+    /// The last instruction of the block
+    pub end: u32,
+    /// Number of instructions in the block
+    pub instructions: usize,
+    /// How often the block was entered at its start in the trace
+    pub executions: u64,
+    /// The block ends with an indirect jump which isn't a call or return
+    indirect: bool,
+}
+
+/// Basic blocks of a single function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionGraph {
+    pub address: u32,
+    pub name: String,
+    /// Sorted by address
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+}
+
+/// Control flow graphs of all functions in the ELF files, annotated with what was traced
+///
+/// Functions are taken from the text symbols. Blocks are split at every branch, jump and
+/// call and at the targets of direct branches and jumps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    /// Sorted by address
+    pub functions: Vec<FunctionGraph>,
+}
+
+impl ControlFlowGraph {
+    pub(crate) fn new(obj_files: &[File<'_>]) -> Self {
+        let mut functions: Vec<FunctionGraph> = Vec::new();
+
+        for obj_file in obj_files {
+            for section in obj_file.sections() {
+                if section.kind() != SectionKind::Text {
+                    continue;
+                }
+                let section_end = section.address() + section.size();
+
+                let mut symbols: Vec<(u64, u64, &str)> = obj_file
+                    .symbols()
+                    .filter(|symbol| {
+                        symbol.kind() == SymbolKind::Text
+                            && symbol.section_index() == Some(section.index())
+                    })
+                    .filter_map(|symbol| {
+                        Some((symbol.address(), symbol.size(), symbol.name().ok()?))
+                    })
+                    .collect();
+                symbols.sort_by_key(|(address, _, _)| *address);
+                symbols.dedup_by_key(|(address, _, _)| *address);
+
+                for (i, &(address, size, name)) in symbols.iter().enumerate() {
+                    // symbols without size information extend to the next one
+                    let end = if size != 0 {
+                        address + size
+                    } else {
+                        symbols.get(i + 1).map_or(section_end, |(next, _, _)| *next)
+                    };
+
+                    if let Ok(Some(data)) = section.data_range(address, end - address) {
+                        if !data.is_empty() {
+                            functions.push(FunctionGraph::new(
+                                address as u32,
+                                format!("{:#}", rustc_demangle::demangle(name)),
+                                data,
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        functions.sort_by_key(|function| function.address);
+
+        Self { functions }
+    }
+
+    /// Count the executed blocks and followed edges of an execution path
+    pub(crate) fn add_execution_path(&mut self, execution_path: &[u32]) {
+        for (i, &pc) in execution_path.iter().enumerate() {
+            if let Some(function) = self.function_mut(pc) {
+                function.step(pc, execution_path.get(i + 1).copied());
+            }
+        }
+    }
+
+    fn function_mut(&mut self, pc: u32) -> Option<&mut FunctionGraph> {
+        let idx = self
+            .functions
+            .partition_point(|function| function.address <= pc);
+        let function = self.functions.get_mut(idx.checked_sub(1)?)?;
+        function.contains(pc).then_some(function)
+    }
+
+    /// Graphviz DOT of every function which was executed, one graph per function
+    pub fn to_dot(&self) -> String {
+        self.functions
+            .iter()
+            .filter(|function| function.executed())
+            .map(|function| function.to_dot())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl FunctionGraph {
+    fn new(address: u32, name: String, data: &[u8]) -> Self {
+        let end = address + data.len() as u32;
+        let insn_at = |pc: u32| -> Option<&[u8]> {
+            let offset = (pc - address) as usize;
+            let len = if data[offset] & 0b11 == 0b11 { 4 } else { 2 };
+            data.get(offset..offset + len)
+        };
+
+        // find the instructions starting a block
+        let mut leaders = vec![address];
+        let mut pc = address;
+        while pc < end {
+            let insn = match insn_at(pc) {
+                Some(insn) => insn,
+                None => break,
+            };
+            let next = pc + insn.len() as u32;
+            if is_inferable_branch(insn) || is_inferable_jump(insn) || is_uninferable_branch(insn) {
+                leaders.push(next);
+            }
+            if is_inferable_branch(insn) || (is_inferable_jump(insn) && !is_call(insn)) {
+                leaders.extend(estimate_next_inferable_pc(insn, pc).last());
+            }
+            pc = next;
+        }
+        leaders.retain(|leader| (address..end).contains(leader));
+        leaders.sort_unstable();
+        leaders.dedup();
+
+        let mut blocks = Vec::new();
+        let mut edges = Vec::new();
+        for (i, &start) in leaders.iter().enumerate() {
+            let block_end = leaders.get(i + 1).copied().unwrap_or(end);
+
+            let mut block = BasicBlock {
+                address: start,
+                end: start,
+                instructions: 0,
+                executions: 0,
+                indirect: false,
+            };
+            let mut pc = start;
+            let mut last = None;
+            while pc < block_end {
+                let insn = match insn_at(pc) {
+                    Some(insn) => insn,
+                    None => break,
+                };
+                block.end = pc;
+                block.instructions += 1;
+                last = Some(insn);
+                pc += insn.len() as u32;
+            }
+
+            let next = pc;
+            let mut edge = |to: u32, kind: EdgeKind| {
+                if (address..end).contains(&to) {
+                    edges.push(Edge {
+                        from: start,
+                        to,
+                        kind,
+                        count: 0,
+                    });
+                }
+            };
+            match last {
+                Some(insn) if is_inferable_branch(insn) => {
+                    edge(next, EdgeKind::NotTaken);
+                    if let Some(&target) = estimate_next_inferable_pc(insn, block.end).last() {
+                        edge(target, EdgeKind::Taken);
+                    }
+                }
+                Some(insn) if is_call(insn) => edge(next, EdgeKind::Call),
+                Some(insn) if is_inferable_jump(insn) => {
+                    if let Some(&target) = estimate_next_inferable_pc(insn, block.end).last() {
+                        edge(target, EdgeKind::Jump);
+                    }
+                }
+                Some(insn) if is_uninferable_branch(insn) => {
+                    block.indirect = !is_return(insn);
+                }
+                Some(_) => edge(next, EdgeKind::Fallthrough),
+                None => (),
+            }
+
+            blocks.push(block);
+        }
+
+        Self {
+            address,
+            name,
+            blocks,
+            edges,
+        }
+    }
+
+    fn contains(&self, pc: u32) -> bool {
+        match self.blocks.last() {
+            Some(block) => pc >= self.address && pc <= block.end,
+            None => false,
+        }
+    }
+
+    fn block_index(&self, pc: u32) -> Option<usize> {
+        let idx = self.blocks.partition_point(|block| block.address <= pc);
+        let idx = idx.checked_sub(1)?;
+        (pc <= self.blocks[idx].end).then_some(idx)
+    }
+
+    fn step(&mut self, pc: u32, next_pc: Option<u32>) {
+        let idx = match self.block_index(pc) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let block = &mut self.blocks[idx];
+        if block.address == pc {
+            block.executions += 1;
+        }
+        if block.end != pc {
+            return;
+        }
+        let (from, indirect) = (block.address, block.indirect);
+
+        if !indirect {
+            for edge in self.edges.iter_mut().filter(|edge| edge.from == from) {
+                // the callee is in another function, count the call when it's made
+                if edge.kind == EdgeKind::Call || Some(edge.to) == next_pc {
+                    edge.count += 1;
+                }
+            }
+            return;
+        }
+
+        // the target of an indirect jump isn't necessarily a block start, e.g. for jump tables
+        let to = match next_pc.and_then(|next_pc| self.block_index(next_pc)) {
+            Some(idx) => self.blocks[idx].address,
+            None => return,
+        };
+        match self
+            .edges
+            .iter_mut()
+            .find(|edge| edge.from == from && edge.to == to)
+        {
+            Some(edge) => edge.count += 1,
+            None => self.edges.push(Edge {
+                from,
+                to,
+                kind: EdgeKind::Indirect,
+                count: 1,
+            }),
+        }
+    }
+
+    /// Whether any of the blocks was executed
+    pub fn executed(&self) -> bool {
+        self.blocks.iter().any(|block| block.executions != 0)
+            || self.edges.iter().any(|edge| edge.count != 0)
+    }
+
+    /// Graphviz DOT, blocks and edges which weren't executed are dashed
+    pub fn to_dot(&self) -> String {
+        let mut res = String::new();
+        let node = |address: u32| format!("\"{:#010x}\"", address);
+
+        writeln!(res, "digraph {} {{", dot_string(&self.name)).unwrap();
+        writeln!(res, "    node [shape=box, fontname=monospace];").unwrap();
+
+        for block in &self.blocks {
+            let offset = block.address - self.address;
+            let label = if offset == 0 {
+                self.name.clone()
+            } else {
+                format!("{}+{:#x}", self.name, offset)
+            };
+            let label = format!(
+                "{}\\n{:#010x}..={:#010x}\\n{} instructions, {} executions",
+                dot_escape(&label),
+                block.address,
+                block.end,
+                block.instructions,
+                block.executions
+            );
+            let style = if block.executions == 0 {
+                ", style=dashed"
+            } else {
+                ""
+            };
+            writeln!(
+                res,
+                "    {} [label=\"{}\"{}];",
+                node(block.address),
+                label,
+                style
+            )
+            .unwrap();
+        }
+
+        for edge in &self.edges {
+            let count = edge.count;
+            let label = match edge.kind {
+                EdgeKind::Taken => format!("taken {}", count),
+                EdgeKind::NotTaken => format!("not taken {}", count),
+                EdgeKind::Call => format!("call {}", count),
+                EdgeKind::Indirect => format!("indirect {}", count),
+                EdgeKind::Fallthrough | EdgeKind::Jump => count.to_string(),
+            };
+            let style = if count == 0 { ", style=dashed" } else { "" };
+            writeln!(
+                res,
+                "    {} -> {} [label=\"{}\"{}];",
+                node(edge.from),
+                node(edge.to),
+                label,
+                style
+            )
+            .unwrap();
+        }
+
+        writeln!(res, "}}").unwrap();
+        res
+    }
+}
+
+fn dot_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn dot_string(value: &str) -> String {
+    format!("\"{}\"", dot_escape(value))
+}
+
+#[test]
+fn test_cfg() {
+    let mut text = vec![0u8; 0x300];
+    // main: c.nop; c.bnez a0, main; jal ra, foo; c.j 0x10c; c.nop; c.nop; ret
+    text[0x100..0x110].copy_from_slice(&[
+        0x01, 0x00, 0x7d, 0xfd, 0xef, 0x00, 0xc0, 0x0f, 0x11, 0xa0, 0x01, 0x00, 0x01, 0x00, 0x82,
+        0x80,
+    ]);
+    // foo: c.jr a0; c.nop; ret
+    text[0x200..0x206].copy_from_slice(&[0x02, 0x85, 0x01, 0x00, 0x82, 0x80]);
+    let elf = crate::test_elf(&text, &[("main", 0x100, 0x10), ("foo", 0x200, 0x6)]);
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    let mut cfg = ControlFlowGraph::new(&obj_files);
+    cfg.add_execution_path(&[
+        0x100, 0x102, 0x100, 0x102, 0x104, 0x200, 0x204, 0x108, 0x10c, 0x10e,
+    ]);
+
+    let main = &cfg.functions[0];
+    let blocks: Vec<(u32, u32, usize, u64)> = main
+        .blocks
+        .iter()
+        .map(|block| {
+            (
+                block.address,
+                block.end,
+                block.instructions,
+                block.executions,
+            )
+        })
+        .collect();
+    assert_eq!(
+        blocks,
+        [
+            (0x100, 0x102, 2, 2),
+            (0x104, 0x104, 1, 1),
+            (0x108, 0x108, 1, 1),
+            (0x10a, 0x10a, 1, 0),
+            (0x10c, 0x10e, 2, 1),
+        ]
+    );
+
+    let edges: Vec<(u32, u32, EdgeKind, u64)> = main
+        .edges
+        .iter()
+        .map(|edge| (edge.from, edge.to, edge.kind, edge.count))
+        .collect();
+    assert_eq!(
+        edges,
+        [
+            (0x100, 0x104, EdgeKind::NotTaken, 1),
+            (0x100, 0x100, EdgeKind::Taken, 1),
+            (0x104, 0x108, EdgeKind::Call, 1),
+            (0x108, 0x10c, EdgeKind::Jump, 1),
+            (0x10a, 0x10c, EdgeKind::Fallthrough, 0),
+        ]
+    );
+
+    // the indirect jump landed in the middle of the following block
+    let foo = &cfg.functions[1];
+    assert_eq!(foo.blocks.len(), 2);
+    assert_eq!(
+        foo.edges,
+        [Edge {
+            from: 0x200,
+            to: 0x202,
+            kind: EdgeKind::Indirect,
+            count: 1,
+        }]
+    );
+
+    let dot = cfg.to_dot();
+    assert!(dot.starts_with("digraph \"main\" {\n"));
+    assert!(dot.contains("\"0x00000100\" -> \"0x00000100\" [label=\"taken 1\"];"));
+    assert!(dot.contains("\"0x0000010a\" -> \"0x0000010c\" [label=\"0\", style=dashed];"));
+    assert!(dot.contains("digraph \"foo\" {\n"));
+}
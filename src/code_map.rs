@@ -0,0 +1,59 @@
+use object::{File, Object, ObjectSection, SectionKind};
+
+/// The executable sections of the ELF files, sorted by address for fast lookups
+pub(crate) struct CodeMap<'a> {
+    sections: Vec<(u32, &'a [u8])>,
+}
+
+impl<'a> CodeMap<'a> {
+    pub(crate) fn new(obj_files: &[File<'a>]) -> Self {
+        let mut sections = Vec::new();
+        for obj_file in obj_files {
+            for section in obj_file.sections() {
+                if section.kind() != SectionKind::Text {
+                    continue;
+                }
+                if let Ok(data) = section.data() {
+                    if !data.is_empty() {
+                        sections.push((section.address() as u32, data));
+                    }
+                }
+            }
+        }
+        sections.sort_by_key(|(address, _)| *address);
+
+        Self { sections }
+    }
+
+    /// The up to 4 bytes at the given address, empty if no section contains it
+    pub(crate) fn instruction(&self, address: u32) -> &'a [u8] {
+        let idx = self
+            .sections
+            .partition_point(|(start, _)| *start <= address);
+
+        for &(start, data) in self.sections[..idx].iter().rev() {
+            let offset = (address - start) as usize;
+            if offset < data.len() {
+                return &data[offset..(offset + 4).min(data.len())];
+            }
+        }
+
+        &[]
+    }
+}
+
+#[test]
+fn test_code_map() {
+    let mut text = vec![0u8; 0x200];
+    text[0x100..0x106].copy_from_slice(&[0x01, 0x00, 0xef, 0x00, 0xc0, 0x0f]);
+    let elf = crate::test_elf(&text, &[]);
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+    let code = CodeMap::new(&obj_files);
+
+    assert_eq!(code.instruction(0x100), [0x01, 0x00, 0xef, 0x00]);
+    assert_eq!(code.instruction(0x1fe), [0x00, 0x00]);
+    assert!(code.instruction(0x200).is_empty());
+    for pc in (0..0x200).step_by(2) {
+        assert_eq!(code.instruction(pc), crate::get_instruction(&obj_files, pc));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::Error;
+
+/// How format 1 and 2 packets report addresses, sync and exception packets always contain the
+/// full address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressMode {
+    #[default]
+    Full,
+    /// The difference to the previously reported address
+    Differential,
+}
+
+/// Parameters of the trace encoder which produced the trace data
+///
+/// The defaults match the encoder of the ESP32-C6 and ESP32-H2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecoderConfig {
+    pub address_mode: AddressMode,
+    /// Number of low address bits which are always 0 and therefore not reported
+    pub iaddress_lsb: u32,
+    /// Number of address bits, including the ones which aren't reported
+    pub iaddress_width: u32,
+    /// Possible widths of the branch map field in ascending order, a packet uses the smallest
+    /// one holding its branches and a full branch map has the largest one
+    pub branch_map_widths: Vec<u32>,
+    /// Whether the encoder emits format 0 packets (branch count and jump target index)
+    pub format0_extensions: bool,
+    /// Number of bits of the jump target index in format 0 packets
+    pub jump_target_cache_size: u32,
+}
+
+impl Default for DecoderConfig {
+    fn default() -> Self {
+        Self {
+            address_mode: AddressMode::Full,
+            iaddress_lsb: 1,
+            iaddress_width: 32,
+            branch_map_widths: vec![1, 3, 7, 15, 31],
+            format0_extensions: false,
+            jump_target_cache_size: 0,
+        }
+    }
+}
+
+impl DecoderConfig {
+    /// Check that the fields described by the configuration fit into a packet
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: &str| {
+            Err(Error::InvalidConfig {
+                message: message.to_string(),
+            })
+        };
+
+        if self.iaddress_width == 0 || self.iaddress_width > 32 {
+            return invalid("the address width has to be 1 to 32 bits");
+        }
+        if self.iaddress_lsb >= self.iaddress_width {
+            return invalid(
+                "the unreported low address bits have to be less than the address width",
+            );
+        }
+        if self.branch_map_widths.is_empty() {
+            return invalid("at least one branch map width is needed");
+        }
+        if !self
+            .branch_map_widths
+            .windows(2)
+            .all(|pair| pair[0] < pair[1])
+        {
+            return invalid("the branch map widths have to be in ascending order");
+        }
+        if self.branch_map_widths[0] == 0 || self.max_branches() > 31 {
+            return invalid("the branch map widths have to be 1 to 31 bits");
+        }
+        if self.jump_target_cache_size > 16 {
+            return invalid("the jump target index can't have more than 16 bits");
+        }
+
+        Ok(())
+    }
+
+    /// Width of the address fields
+    pub(crate) fn address_bits(&self) -> usize {
+        (self.iaddress_width - self.iaddress_lsb) as usize
+    }
+
+    /// Width of the branch map of a packet with the given number of branches, `None` if they
+    /// don't fit
+    pub(crate) fn branch_map_bits(&self, branches: u32) -> Option<usize> {
+        self.branch_map_widths
+            .iter()
+            .find(|width| **width >= branches)
+            .map(|width| *width as usize)
+    }
+
+    /// Number of branches in a full branch map
+    pub(crate) fn max_branches(&self) -> u32 {
+        *self.branch_map_widths.last().unwrap()
+    }
+
+    /// Turn an address field into an address, `previous` is the last reported address
+    pub(crate) fn address(&self, field: u32, previous: u32, differential: bool) -> u32 {
+        let bits = self.address_bits();
+        let address = if differential {
+            // the difference is sign extended
+            let difference = ((field << (32 - bits)) as i32 >> (32 - bits)) as u32;
+            previous.wrapping_add(difference << self.iaddress_lsb)
+        } else {
+            field << self.iaddress_lsb
+        };
+
+        address & self.address_mask()
+    }
+
+    /// Turn an address into an address field, the inverse of [DecoderConfig::address]
+    pub(crate) fn address_field(&self, address: u32, previous: u32, differential: bool) -> u32 {
+        let field = match differential {
+            true => address.wrapping_sub(previous) >> self.iaddress_lsb,
+            false => address >> self.iaddress_lsb,
+        };
+        field & (u32::MAX >> (32 - self.address_bits()))
+    }
+
+    fn address_mask(&self) -> u32 {
+        u32::MAX >> (32 - self.iaddress_width)
+    }
+
+    /// Check if an address can be reported, i.e. the low bits are 0 and it fits the address
+    /// width
+    pub(crate) fn is_reportable(&self, address: u32) -> bool {
+        address & ((1 << self.iaddress_lsb) - 1) == 0 && address & !self.address_mask() == 0
+    }
+}
+
+#[test]
+fn test_validate() {
+    assert!(DecoderConfig::default().validate().is_ok());
+
+    let invalid = [
+        DecoderConfig {
+            iaddress_width: 33,
+            ..Default::default()
+        },
+        DecoderConfig {
+            iaddress_lsb: 32,
+            ..Default::default()
+        },
+        DecoderConfig {
+            branch_map_widths: vec![3, 1],
+            ..Default::default()
+        },
+        DecoderConfig {
+            branch_map_widths: vec![1, 32],
+            ..Default::default()
+        },
+        DecoderConfig {
+            branch_map_widths: Vec::new(),
+            ..Default::default()
+        },
+    ];
+    for config in invalid {
+        assert!(matches!(
+            config.validate(),
+            Err(Error::InvalidConfig { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_address() {
+    let config = DecoderConfig::default();
+    assert_eq!(config.address(0x80, 0x4000_0000, false), 0x100);
+    assert_eq!(config.address_field(0x100, 0x4000_0000, false), 0x80);
+
+    // backwards and forwards relative to the previous address
+    for (address, previous) in [(0x4000_0100, 0x4000_0200), (0x4200_0000, 0x4000_0200)] {
+        let field = config.address_field(address, previous, true);
+        assert_eq!(config.address(field, previous, true), address);
+    }
+    assert_eq!(config.address(0x7fff_ff80, 0x4000_0200, true), 0x4000_0100);
+
+    let config = DecoderConfig {
+        iaddress_lsb: 2,
+        iaddress_width: 24,
+        ..Default::default()
+    };
+    assert_eq!(config.address_bits(), 22);
+    assert_eq!(config.address(0x3f_ffff, 0, false), 0xff_fffc);
+    assert_eq!(config.address(0x3f_ffff, 0x10, true), 0xc);
+    assert!(config.is_reportable(0xff_fffc));
+    assert!(!config.is_reportable(0x100_0000));
+    assert!(!config.is_reportable(0x2));
+}
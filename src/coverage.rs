@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use object::{File, Object, ObjectSection, SectionKind};
+
+use crate::event::TraceEvent;
+use crate::inst_decoder::is_inferable_branch;
+
+/// Source line and branch coverage of one or more traces
+///
+/// Every instruction covered by the DWARF line tables of the ELF files is known, so lines and
+/// branches which were never executed are reported too.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    files: Vec<String>,
+    /// File index and line of every instruction with line information
+    locations: HashMap<u32, (usize, u32)>,
+    /// How often each instruction was executed
+    executions: HashMap<u32, u64>,
+    /// How often each branch instruction was taken and not taken
+    branches: HashMap<u32, (u64, u64)>,
+}
+
+impl Coverage {
+    pub(crate) fn new(obj_files: &[File<'_>]) -> Self {
+        let mut coverage = Self::default();
+
+        for obj_file in obj_files {
+            // ELFs without debug info don't contribute any lines
+            let context = match addr2line::Context::new(obj_file) {
+                Ok(context) => context,
+                Err(_) => continue,
+            };
+
+            for section in obj_file.sections() {
+                if section.kind() != SectionKind::Text {
+                    continue;
+                }
+                let data = match section.data() {
+                    Ok(data) => data,
+                    Err(_) => continue,
+                };
+                let start = section.address();
+                let end = start + section.size();
+
+                let rows = match context.find_location_range(start, end) {
+                    Ok(rows) => rows,
+                    Err(_) => continue,
+                };
+
+                for (address, len, location) in rows {
+                    let (file, line) = match (location.file, location.line) {
+                        (Some(file), Some(line)) => (file, line),
+                        _ => continue,
+                    };
+                    let file = coverage.file_index(file);
+
+                    // line table rows start at instruction boundaries
+                    let mut pc = address;
+                    while pc < (address + len).min(end) {
+                        let offset = (pc - start) as usize;
+                        let insn = &data[offset..(offset + 4).min(data.len())];
+                        let insn_len = if insn[0] & 0b11 == 0b11 { 4 } else { 2 };
+                        if insn.len() < insn_len {
+                            break;
+                        }
+
+                        coverage.locations.insert(pc as u32, (file, line));
+                        if is_inferable_branch(&insn[..insn_len]) {
+                            coverage.branches.entry(pc as u32).or_default();
+                        }
+                        pc += insn_len as u64;
+                    }
+                }
+            }
+        }
+
+        coverage
+    }
+
+    fn file_index(&mut self, file: &str) -> usize {
+        match self.files.iter().position(|known| known == file) {
+            Some(index) => index,
+            None => {
+                self.files.push(file.to_string());
+                self.files.len() - 1
+            }
+        }
+    }
+
+    /// Count the executed instructions and branch outcomes of a decoded trace
+    pub(crate) fn add_events(&mut self, events: &[TraceEvent]) {
+        for event in events {
+            match *event {
+                TraceEvent::Instruction { pc, .. } => *self.executions.entry(pc).or_default() += 1,
+                TraceEvent::BranchTaken { from, .. } => {
+                    self.branches.entry(from).or_default().0 += 1
+                }
+                TraceEvent::BranchNotTaken { pc } => self.branches.entry(pc).or_default().1 += 1,
+                _ => (),
+            }
+        }
+    }
+
+    /// Add the counts of another coverage, e.g. of a different trace of the same firmware
+    pub fn merge(&mut self, other: &Coverage) {
+        for (pc, (file, line)) in &other.locations {
+            let file = self.file_index(&other.files[*file]);
+            self.locations.insert(*pc, (file, *line));
+        }
+        for (pc, count) in &other.executions {
+            *self.executions.entry(*pc).or_default() += count;
+        }
+        for (pc, (taken, not_taken)) in &other.branches {
+            let branch = self.branches.entry(*pc).or_default();
+            branch.0 += taken;
+            branch.1 += not_taken;
+        }
+    }
+
+    /// Line and branch information per file, branches are listed per line in address order
+    #[allow(clippy::type_complexity)]
+    fn lines(&self) -> BTreeMap<&str, (BTreeMap<u32, u64>, BTreeMap<u32, Vec<u32>>)> {
+        let mut files: BTreeMap<&str, (BTreeMap<u32, u64>, BTreeMap<u32, Vec<u32>>)> =
+            BTreeMap::new();
+
+        for (pc, (file, line)) in &self.locations {
+            let (lines, branches) = files.entry(&self.files[*file]).or_default();
+
+            let executions = self.executions.get(pc).copied().unwrap_or_default();
+            let hits = lines.entry(*line).or_default();
+            *hits = (*hits).max(executions);
+
+            if self.branches.contains_key(pc) {
+                branches.entry(*line).or_default().push(*pc);
+            }
+        }
+
+        for (_, branches) in files.values_mut() {
+            for pcs in branches.values_mut() {
+                pcs.sort_unstable();
+            }
+        }
+
+        files
+    }
+
+    /// Render the coverage as an lcov tracefile
+    ///
+    /// Every branch instruction is a block with the taken (0) and not taken (1) branch.
+    pub fn to_lcov(&self) -> String {
+        let mut out = String::new();
+
+        for (file, (lines, branches)) in self.lines() {
+            writeln!(out, "TN:").unwrap();
+            writeln!(out, "SF:{}", file).unwrap();
+
+            let mut found = 0;
+            let mut hit = 0;
+            for (line, pcs) in &branches {
+                for (block, pc) in pcs.iter().enumerate() {
+                    let (taken, not_taken) = self.branches[pc];
+                    let executed = self.executions.contains_key(pc) || taken + not_taken != 0;
+
+                    for (branch, count) in [taken, not_taken].into_iter().enumerate() {
+                        found += 1;
+                        if !executed {
+                            writeln!(out, "BRDA:{},{},{},-", line, block, branch).unwrap();
+                            continue;
+                        }
+                        if count != 0 {
+                            hit += 1;
+                        }
+                        writeln!(out, "BRDA:{},{},{},{}", line, block, branch, count).unwrap();
+                    }
+                }
+            }
+            writeln!(out, "BRF:{}", found).unwrap();
+            writeln!(out, "BRH:{}", hit).unwrap();
+
+            for (line, hits) in &lines {
+                writeln!(out, "DA:{},{}", line, hits).unwrap();
+            }
+            writeln!(out, "LF:{}", lines.len()).unwrap();
+            writeln!(
+                out,
+                "LH:{}",
+                lines.values().filter(|hits| **hits != 0).count()
+            )
+            .unwrap();
+            writeln!(out, "end_of_record").unwrap();
+        }
+
+        out
+    }
+}
+
+#[test]
+fn test_lcov() {
+    let (_, trace) = crate::decoder::test_trace();
+    let mut text = vec![0u8; 0x300];
+    text[0x100..0x106].copy_from_slice(&[0x01, 0x00, 0x7d, 0xfd, 0x82, 0x80]);
+    text[0x200..0x206].copy_from_slice(&[0x01, 0x00, 0x01, 0x00, 0x01, 0x00]);
+    let elf = crate::test_elf_with_lines(
+        &text,
+        &[],
+        "main.c",
+        &[
+            (0x100, 10),
+            (0x102, 11),
+            (0x104, 12),
+            (0x200, 20),
+            (0x206, 21),
+        ],
+    );
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    let mut coverage = Coverage::new(&obj_files);
+    coverage
+        .add_events(&crate::decode(&trace, &obj_files, &crate::DecoderConfig::default()).unwrap());
+
+    assert_eq!(
+        coverage.to_lcov(),
+        "TN:\n\
+         SF:/src/main.c\n\
+         BRDA:11,0,0,2\n\
+         BRDA:11,0,1,1\n\
+         BRF:2\n\
+         BRH:2\n\
+         DA:10,3\n\
+         DA:11,3\n\
+         DA:12,1\n\
+         DA:20,1\n\
+         DA:21,0\n\
+         LF:5\n\
+         LH:4\n\
+         end_of_record\n"
+    );
+
+    let mut merged = Coverage::new(&obj_files);
+    merged.merge(&coverage);
+    merged.merge(&coverage);
+    let lcov = merged.to_lcov();
+    assert!(lcov.contains("BRDA:11,0,0,4\n"));
+    assert!(lcov.contains("DA:10,6\n"));
+    assert!(lcov.contains("DA:21,0\n"));
+
+    // branches which were never reached
+    let coverage = Coverage::new(&obj_files);
+    let lcov = coverage.to_lcov();
+    assert!(lcov.contains("BRDA:11,0,0,-\nBRDA:11,0,1,-\nBRF:2\nBRH:0\n"));
+}
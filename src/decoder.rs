@@ -0,0 +1,1428 @@
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use object::File;
+
+use crate::branch_map::BranchMap;
+use crate::code_map::CodeMap;
+use crate::config::DecoderConfig;
+use crate::event::TraceEvent;
+use crate::inst_decoder::{
+    is_inferable_branch, is_inferable_jump, is_return, is_trap_return, is_uninferable_branch,
+    next_address,
+};
+use crate::trace_decoder::{AddressBranchMap, Packet, PacketParser, Support};
+use crate::trap::Trap;
+use crate::Error;
+
+/// Output of the [Decoder]
+#[derive(Debug, Clone, Copy)]
+pub enum Decoded {
+    /// A packet parsed from the trace data
+    Packet(Packet),
+    /// The next event of the reconstructed execution
+    Event(TraceEvent),
+}
+
+/// How a block of instructions ends
+#[derive(Debug, Clone, Copy)]
+enum BlockEnd {
+    /// The following instruction couldn't be read, the error is reported once it's reached
+    Continue(u32),
+    Branch {
+        taken: u32,
+        not_taken: u32,
+    },
+    Jump(u32),
+    /// The target is reported by the next packet
+    Uninferable {
+        is_return: bool,
+    },
+}
+
+/// Straight line instructions up to and including the next branch or jump
+#[derive(Debug)]
+struct Block {
+    /// PC and encoding of every instruction
+    instructions: Vec<(u32, u32)>,
+    end: BlockEnd,
+}
+
+/// Incremental decoder which can be fed with trace data as it arrives
+///
+/// Reconstruction lags two packets behind the parsed packets since the end of the trace
+/// (an address packet followed by a support packet) needs to be known before walking up to it.
+pub struct Decoder<'a> {
+    code: CodeMap<'a>,
+    /// Decoded blocks by start address, loops are only decoded once
+    blocks: HashMap<u32, Rc<Block>>,
+    parser: PacketParser,
+    config: DecoderConfig,
+    /// Targets of uninferable jumps, indexed by their low address bits
+    jump_targets: Vec<u32>,
+    packets: VecDeque<Packet>,
+    output: VecDeque<Decoded>,
+
+    packet_count: usize,
+    last_index: Option<u32>,
+    started: bool,
+    synced: bool,
+    done: bool,
+    /// Index of the packet reporting the last address and the address
+    end: Option<(u32, u32)>,
+    pc: u32,
+    last_pc: Option<u32>,
+    branch_map: BranchMap,
+    uninferable: bool,
+    uninferable_return: bool,
+    /// The reported jump target is the last instruction before the next format 3 packet
+    updiscon: bool,
+    branch_map_taken: bool,
+    needs_branches: bool,
+}
+
+impl<'a> Decoder<'a> {
+    /// Create a decoder using the given ELF files
+    pub fn new(obj_files: &'a [File<'a>]) -> Self {
+        Self::with_config(obj_files, DecoderConfig::default()).unwrap()
+    }
+
+    /// Create a decoder for traces of an encoder with the given configuration
+    pub fn with_config(obj_files: &'a [File<'a>], config: DecoderConfig) -> Result<Self, Error> {
+        Ok(Self {
+            code: CodeMap::new(obj_files),
+            blocks: HashMap::new(),
+            parser: PacketParser::with_config(config.clone())?,
+            jump_targets: vec![0; 1 << config.jump_target_cache_size],
+            config,
+            packets: VecDeque::new(),
+            output: VecDeque::new(),
+
+            packet_count: 0,
+            last_index: None,
+            started: false,
+            synced: false,
+            done: false,
+            end: None,
+            pc: 0,
+            last_pc: None,
+            branch_map: BranchMap::new(),
+            uninferable: false,
+            uninferable_return: false,
+            updiscon: false,
+            branch_map_taken: false,
+            needs_branches: false,
+        })
+    }
+
+    /// Feed the next chunk of trace data and get everything decoded so far
+    pub fn push(&mut self, data: &[u8]) -> Result<impl Iterator<Item = Decoded> + '_, Error> {
+        self.parser.push(data);
+        self.parse()?;
+        self.reconstruct(false)?;
+
+        Ok(self.output.drain(..))
+    }
+
+    /// Signal the end of the trace data and get the remaining decoded output
+    pub fn finish(&mut self) -> Result<impl Iterator<Item = Decoded> + '_, Error> {
+        self.parser.finish();
+        self.parse()?;
+        self.reconstruct(true)?;
+
+        Ok(self.output.drain(..))
+    }
+
+    /// Returns true once the end of the trace was reached
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn parse(&mut self) -> Result<(), Error> {
+        while let Some(packet) = self.parser.next_packet()? {
+            log::debug!("Parsed {:#x?}", &packet);
+
+            if self.packet_count == 0 && self.parser.discarded() != 0 {
+                let bytes = self.parser.discarded();
+                log::warn!("discarded {} bytes before the first packet", bytes);
+                self.event(TraceEvent::Discarded { bytes });
+            }
+
+            self.output.push_back(Decoded::Packet(packet));
+            self.packets.push_back(packet);
+            self.packet_count += 1;
+        }
+
+        Ok(())
+    }
+
+    fn event(&mut self, event: TraceEvent) {
+        self.output.push_back(Decoded::Event(event));
+    }
+
+    fn support(&mut self, support: Support) {
+        if support.is_trace_lost() {
+            log::warn!("the encoder lost packets");
+            self.event(TraceEvent::TraceLost);
+            self.drop_state();
+            if support.enable {
+                return;
+            }
+        }
+
+        self.event(if support.enable {
+            TraceEvent::TraceStart
+        } else {
+            TraceEvent::TraceStop
+        });
+    }
+
+    fn advance(&mut self) {
+        if let Some(packet) = self.packets.pop_front() {
+            self.last_index = Some(packet.index());
+        }
+        self.branch_map_taken = false;
+    }
+
+    /// Packets got lost, drop the current state and wait for the next sync packet
+    fn gap(&mut self, expected: u32, index: u32) {
+        log::warn!("packet index gap, expected {} got {}", expected, index);
+        self.event(TraceEvent::Gap { expected, index });
+        self.drop_state();
+    }
+
+    /// Wait for the next sync packet, the packets up to it can't be reconstructed
+    fn drop_state(&mut self) {
+        log::debug!("dropping {} branches", self.branch_map.len());
+
+        self.last_index = None;
+        self.started = false;
+        self.last_pc = None;
+        self.branch_map.clear();
+        self.uninferable = false;
+        self.uninferable_return = false;
+        self.updiscon = false;
+        self.branch_map_taken = false;
+        self.needs_branches = false;
+    }
+
+    /// Continue at the reported target of an uninferable jump
+    fn jump(&mut self, to: u32) {
+        if self.uninferable {
+            let index = self.jump_target_index(to);
+            self.jump_targets[index] = to;
+
+            let from = self.pc;
+            self.event(if self.uninferable_return {
+                TraceEvent::Return { from, to }
+            } else {
+                TraceEvent::IndirectJump { from, to }
+            });
+        }
+        self.pc = to;
+    }
+
+    fn jump_target_index(&self, address: u32) -> usize {
+        (address >> self.config.iaddress_lsb) as usize & (self.jump_targets.len() - 1)
+    }
+
+    fn instruction(&self, pc: u32) -> Result<&'a [u8], Error> {
+        let insn = self.code.instruction(pc);
+
+        if insn.is_empty() {
+            return Err(Error::InstructionNotFound { pc });
+        }
+
+        // a 32 bit instruction at the very end of a section or the defined illegal instruction
+        if (insn[0] & 0b11 == 0b11 && insn.len() < 4) || insn[0..2] == [0, 0] {
+            return Err(Error::IllegalInstruction {
+                pc,
+                bytes: insn.to_vec(),
+            });
+        }
+
+        Ok(insn)
+    }
+
+    /// The block starting at the given PC, decoded on first use
+    fn block(&mut self, start: u32) -> Result<Rc<Block>, Error> {
+        if let Some(block) = self.blocks.get(&start) {
+            return Ok(block.clone());
+        }
+
+        let mut instructions = Vec::new();
+        let mut pc = start;
+        let end = loop {
+            let insn = match self.instruction(pc) {
+                Ok(insn) => insn,
+                Err(_) if !instructions.is_empty() => break BlockEnd::Continue(pc),
+                Err(err) => return Err(err),
+            };
+
+            let raw = if insn[0] & 0b11 == 0b11 {
+                u32::from_le_bytes(insn[..4].try_into().unwrap())
+            } else {
+                u16::from_le_bytes(insn[..2].try_into().unwrap()) as u32
+            };
+            instructions.push((pc, raw));
+
+            let next = next_address(insn, pc);
+            if is_inferable_branch(insn) {
+                break BlockEnd::Branch {
+                    taken: next.branched.unwrap(),
+                    not_taken: next.next_instruction.unwrap(),
+                };
+            } else if is_inferable_jump(insn) {
+                break BlockEnd::Jump(next.next_instruction.unwrap());
+            } else if is_uninferable_branch(insn) {
+                break BlockEnd::Uninferable {
+                    is_return: is_return(insn) || is_trap_return(insn),
+                };
+            }
+            pc = next.next_instruction.unwrap();
+        };
+
+        let block = Rc::new(Block { instructions, end });
+        log::debug!("decoded block {:#x?}", block);
+        self.blocks.insert(start, block.clone());
+        Ok(block)
+    }
+
+    fn find_end(&mut self, finished: bool) -> Result<(), Error> {
+        if !finished {
+            // the end can only be detected once the support packet arrived
+            let end = self
+                .packets
+                .iter()
+                .zip(self.packets.iter().skip(1))
+                .take(2)
+                .find_map(|(packet, next)| match next {
+                    Packet::Support(_, support) if !support.is_trace_lost() => end_address(packet),
+                    _ => None,
+                });
+            self.end = end;
+            return Ok(());
+        }
+
+        let last_packet = match self.packets.back() {
+            Some(Packet::Support(_, _)) => self.packets.len().checked_sub(2),
+            Some(_) => {
+                log::debug!("Last packet is not a support packet. Data corrupted?");
+                Some(self.packets.len() - 1)
+            }
+            None => None,
+        };
+
+        if let Some(end) = last_packet
+            .and_then(|idx| self.packets.get(idx))
+            .and_then(end_address)
+        {
+            self.end = Some(end);
+            Ok(())
+        } else {
+            log::debug!("No data packet before support ending packet.");
+            Err(Error::Corrupted)
+        }
+    }
+
+    /// Check if the end of the trace is reached at `pc`
+    ///
+    /// The last address can be executed before, e.g. in a loop, so all branches and packets in
+    /// front of the packet reporting it need to be consumed.
+    fn is_end(&self, pc: u32) -> bool {
+        let index = match self.end {
+            Some((index, end_pc)) if end_pc == pc => index,
+            _ => return false,
+        };
+
+        if !self.branch_map.is_empty() {
+            return false;
+        }
+        match self.packets.front() {
+            // the end packet itself may have been consumed as the target of a jump
+            Some(packet) if packet.index() == (index + 1) & 0xffff => true,
+            // its branches come before the end
+            Some(Packet::AddressBranchMap(packet_index, _)) => {
+                *packet_index == index && self.branch_map_taken
+            }
+            Some(packet) => packet.index() == index,
+            None => true,
+        }
+    }
+
+    fn reconstruct(&mut self, finished: bool) -> Result<(), Error> {
+        'outer: loop {
+            if self.done {
+                // nothing to reconstruct after the end but report the encoder stopping
+                while let Some(packet) = self.packets.pop_front() {
+                    if let Packet::Support(_, support) = packet {
+                        self.support(support);
+                    }
+                }
+                return Ok(());
+            }
+
+            if let (Some(last), Some(packet)) = (self.last_index, self.packets.front()) {
+                let expected = (last + 1) & 0xffff;
+                if packet.index() != expected {
+                    self.gap(expected, packet.index());
+                }
+            }
+
+            if !self.started {
+                match self.packets.front() {
+                    Some(Packet::Sync(_, _)) => {
+                        log::debug!("first sync packet");
+                        self.started = true;
+                        self.synced = true;
+                    }
+                    Some(packet) => {
+                        if let Packet::Support(_, support) = *packet {
+                            self.support(support);
+                        }
+                        self.advance();
+                        continue;
+                    }
+                    None if finished && self.packet_count == 0 => return Err(Error::EmptyTrace),
+                    None if finished && !self.synced => return Err(Error::NoSyncPacket),
+                    None => return Ok(()),
+                }
+            }
+
+            if !finished && self.packets.len() < 3 {
+                return Ok(());
+            }
+
+            if self.end.is_none() {
+                self.find_end(finished)?;
+            }
+
+            let packet = match self.packets.front() {
+                Some(packet) => *packet,
+                None if finished && self.needs_branches => {
+                    return Err(Error::BranchMapUnderflow {
+                        packet_index: self.last_index.unwrap_or_default(),
+                    })
+                }
+                None => return Ok(()),
+            };
+
+            // a cached jump target is handled like a reported one
+            let packet = match packet {
+                Packet::JumpTargetIndex(index, jump) => Packet::AddressBranchMap(
+                    index,
+                    AddressBranchMap {
+                        address: self.jump_targets[jump.cache_index as usize],
+                        branches: jump.branches,
+                        branch_map: jump.branch_map,
+                        notify: jump.notify,
+                        updiscon: jump.updiscon,
+                    },
+                ),
+                packet => packet,
+            };
+
+            self.updiscon = false;
+            match packet {
+                Packet::Sync(_, sync) => {
+                    // should a sync be considered an address for uninferable branches?
+                    self.pc = sync.address;
+                    self.event(TraceEvent::Resync { pc: sync.address });
+
+                    let insn = self.instruction(self.pc)?;
+                    // if an inferable branch -> push if it should be taken or not
+                    if is_inferable_branch(insn) {
+                        log::debug!(
+                            "sync is an inferable branch, branch taken = {}",
+                            !sync.branch
+                        );
+                        self.branch_map.push_front(!sync.branch);
+                    }
+                    self.advance();
+                }
+                Packet::Exception(_, exception) => {
+                    let trap = Trap {
+                        cause: exception.ecause,
+                        interrupt: exception.interrupt,
+                        epc: exception.tvalepc,
+                        handler: exception.address,
+                    };
+                    log::debug!("trap: {}", trap);
+                    self.event(TraceEvent::Trap(trap));
+
+                    self.pc = exception.address;
+
+                    let insn = self.instruction(self.pc)?;
+                    if is_inferable_branch(insn) {
+                        self.branch_map.push_front(!exception.branch);
+                    }
+                    self.advance();
+                }
+                Packet::Address(_, address)
+                    if address.notify && !self.uninferable && !self.needs_branches =>
+                {
+                    // reached the reported instruction, continue after it
+                    self.event(TraceEvent::Notify {
+                        pc: address.address,
+                    });
+                    self.advance();
+                }
+                Packet::Address(index, address) => {
+                    if self.needs_branches
+                        && Some(address.address) != self.end.map(|(_, end_pc)| end_pc)
+                    {
+                        return Err(Error::BranchMapUnderflow {
+                            packet_index: index,
+                        });
+                    }
+
+                    self.updiscon = address.updiscon;
+                    self.jump(address.address);
+                    self.advance();
+                }
+                Packet::AddressBranchMap(_, map) => {
+                    if self.uninferable {
+                        self.updiscon = map.updiscon;
+                        self.jump(map.address);
+                        self.advance();
+                    } else if self.branch_map_taken && map.notify && !self.needs_branches {
+                        self.event(TraceEvent::Notify { pc: map.address });
+                        self.advance();
+                    } else if self.branch_map_taken {
+                        // ran out of branches before reaching the reported address
+                        self.advance();
+                        continue;
+                    } else {
+                        self.branch_map_taken = true;
+                        self.branch_map
+                            .push_packet(map.branches as u32, map.branch_map);
+                    }
+                }
+                Packet::NoAddressBranchMap(_, map) => {
+                    // a full map, the branch count is 0
+                    self.branch_map
+                        .push_packet(self.config.max_branches(), map.branch_map);
+                    self.advance();
+                }
+                Packet::BranchCount(index, _) => {
+                    // predicting branches isn't modelled
+                    return Err(Error::UnsupportedPacket {
+                        packet_index: index,
+                    });
+                }
+                Packet::JumpTargetIndex(..) => unreachable!(),
+                Packet::Support(_, support) => {
+                    self.support(support);
+                    self.advance();
+                    continue;
+                }
+            }
+            self.uninferable = false;
+            self.needs_branches = false;
+
+            loop {
+                // once all reported branches are consumed the block ends at the trapping
+                // instruction, an interrupted instruction doesn't get executed
+                let next = match self.packets.front() {
+                    // the branches before the trap come with the last address before it
+                    Some(Packet::AddressBranchMap(..)) if self.branch_map_taken => {
+                        self.packets.get(1)
+                    }
+                    packet => packet,
+                };
+                let trap_at = match next {
+                    Some(Packet::Exception(_, exception)) if self.branch_map.is_empty() => {
+                        Some((exception.tvalepc, exception.interrupt))
+                    }
+                    _ => None,
+                };
+                if trap_at == Some((self.pc, true)) {
+                    continue 'outer;
+                }
+                // a notification reports an instruction on the inferred path
+                let notify_at = match self.packets.front() {
+                    Some(Packet::Address(_, address)) if address.notify => Some(address.address),
+                    Some(Packet::AddressBranchMap(_, map))
+                        if map.notify && self.branch_map_taken =>
+                    {
+                        Some(map.address)
+                    }
+                    _ => None,
+                };
+
+                let block = self.block(self.pc)?;
+                for &(pc, insn) in &block.instructions {
+                    self.pc = pc;
+                    if trap_at == Some((pc, true)) {
+                        continue 'outer;
+                    }
+
+                    log::debug!("PC={:x} instruction {:x}", pc, insn);
+                    if self.last_pc != Some(pc) {
+                        self.event(TraceEvent::Instruction { pc, insn });
+                        self.last_pc = Some(pc);
+                    }
+
+                    if notify_at == Some(pc) && self.branch_map.is_empty() {
+                        continue 'outer;
+                    }
+
+                    if self.is_end(pc) {
+                        self.done = true;
+                        continue 'outer;
+                    }
+
+                    if trap_at == Some((pc, false)) {
+                        continue 'outer;
+                    }
+
+                    // nothing after the reported jump target runs before the format 3 packet
+                    if self.updiscon {
+                        self.updiscon = false;
+                        if matches!(
+                            self.packets.front(),
+                            Some(Packet::Sync(..) | Packet::Exception(..) | Packet::Support(..))
+                        ) {
+                            continue 'outer;
+                        }
+                    }
+                }
+
+                let pc = self.pc;
+                match block.end {
+                    BlockEnd::Continue(next) | BlockEnd::Jump(next) => self.pc = next,
+                    BlockEnd::Branch { taken, not_taken } => {
+                        let branch_taken = match self.branch_map.pop() {
+                            Some(branch_taken) => branch_taken,
+                            None => {
+                                log::debug!("empty branch map");
+                                self.needs_branches = true;
+                                continue 'outer;
+                            }
+                        };
+
+                        self.pc = if branch_taken {
+                            self.event(TraceEvent::BranchTaken {
+                                from: pc,
+                                to: taken,
+                            });
+                            taken
+                        } else {
+                            self.event(TraceEvent::BranchNotTaken { pc });
+                            not_taken
+                        };
+                    }
+                    BlockEnd::Uninferable { is_return } => {
+                        log::info!("uninferable branch");
+                        self.uninferable = true;
+                        self.uninferable_return = is_return;
+                        continue 'outer;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The last address reported by a packet followed by the support packet stopping the trace
+fn end_address(packet: &Packet) -> Option<(u32, u32)> {
+    match packet {
+        Packet::Address(index, addr) => Some((*index, addr.address)),
+        Packet::AddressBranchMap(index, map) => Some((*index, map.address)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_packet(fields: &[(u32, usize)]) -> Vec<u8> {
+    let bits: usize = 8 + fields.iter().map(|(_, width)| width).sum::<usize>();
+    let len = bits.div_ceil(8);
+    let mut res = vec![0u8; len];
+    res[0] = len as u8;
+
+    let mut pos = 8;
+    for (value, width) in fields {
+        for i in 0..*width {
+            if (value >> i) & 1 != 0 {
+                res[pos / 8] |= 1 << (pos % 8);
+            }
+            pos += 1;
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+pub(crate) fn test_trace() -> (Vec<u8>, Vec<u8>) {
+    let mut text = vec![0u8; 0x300];
+    // loop: c.nop; c.bnez a0, loop; ret
+    text[0x100..0x106].copy_from_slice(&[0x01, 0x00, 0x7d, 0xfd, 0x82, 0x80]);
+    // c.nop; c.nop; c.nop
+    text[0x200..0x206].copy_from_slice(&[0x01, 0x00, 0x01, 0x00, 0x01, 0x00]);
+
+    let mut trace = Vec::new();
+    // sync
+    trace.extend(test_packet(&[
+        (0, 16),
+        (0b11, 2),
+        (0b00, 2),
+        (0, 1),
+        (0, 1),
+        (0x100 >> 1, 31),
+        (0, 3),
+    ]));
+    // branch map taken, taken, not taken + address
+    trace.extend(test_packet(&[
+        (1, 16),
+        (0b01, 2),
+        (3, 5),
+        (0b100, 3),
+        (0x200 >> 1, 31),
+        (0, 1),
+        (0, 1),
+        (0, 5),
+    ]));
+    // final address
+    trace.extend(test_packet(&[
+        (2, 16),
+        (0b10, 2),
+        (0x204 >> 1, 31),
+        (0, 1),
+        (0, 1),
+        (0, 5),
+    ]));
+    // support
+    trace.extend(test_packet(&[
+        (3, 16),
+        (0b11, 2),
+        (0b11, 2),
+        (0, 1),
+        (0, 2),
+        (0, 1),
+    ]));
+
+    (crate::test_elf(&text, &[]), trace)
+}
+
+#[cfg(test)]
+pub(crate) const TEST_TRACE_PATH: &[u32] = &[
+    0x100, 0x102, 0x100, 0x102, 0x100, 0x102, 0x104, 0x200, 0x202, 0x204,
+];
+
+#[test]
+fn test_decode_whole_trace() {
+    let (elf, trace) = test_trace();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    let execution_path = crate::reconstruct(&trace, &obj_files, &DecoderConfig::default()).unwrap();
+    assert_eq!(execution_path, TEST_TRACE_PATH);
+}
+
+#[test]
+fn test_decode_in_chunks() {
+    let (elf, trace) = test_trace();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    let mut decoder = Decoder::new(&obj_files);
+    let mut packets = 0;
+    let mut execution_path = Vec::new();
+    for chunk in trace.chunks(3) {
+        for decoded in decoder.push(chunk).unwrap() {
+            match decoded {
+                Decoded::Packet(_) => packets += 1,
+                Decoded::Event(TraceEvent::Instruction { pc, .. }) => execution_path.push(pc),
+                Decoded::Event(_) => (),
+            }
+        }
+    }
+    assert!(decoder.is_done());
+    assert_eq!(decoder.finish().unwrap().count(), 0);
+
+    assert_eq!(packets, 4);
+    assert_eq!(execution_path, TEST_TRACE_PATH);
+}
+
+#[test]
+fn test_decode_errors() {
+    let (elf, trace) = test_trace();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    assert!(matches!(
+        crate::reconstruct(&[], &obj_files, &DecoderConfig::default()),
+        Err(Error::EmptyTrace)
+    ));
+
+    // only the support packet
+    assert!(matches!(
+        crate::reconstruct(&trace[25..], &obj_files, &DecoderConfig::default()),
+        Err(Error::NoSyncPacket)
+    ));
+
+    // an address packet while the outcome of the loop's branch is still unknown
+    let mut unexpected_address = trace[..8].to_vec();
+    unexpected_address.extend(test_packet(&[
+        (1, 16),
+        (0b10, 2),
+        (0x200 >> 1, 31),
+        (0, 1),
+        (0, 1),
+        (0, 5),
+    ]));
+    unexpected_address.extend(&trace[17..]);
+    assert!(matches!(
+        crate::reconstruct(&unexpected_address, &obj_files, &DecoderConfig::default()),
+        Err(Error::BranchMapUnderflow { packet_index: 1 })
+    ));
+
+    // sync to an address outside of the ELF
+    let mut trace = test_packet(&[
+        (0, 16),
+        (0b11, 2),
+        (0b00, 2),
+        (0, 1),
+        (0, 1),
+        (0x1000 >> 1, 31),
+        (0, 3),
+    ]);
+    trace.extend(test_packet(&[
+        (1, 16),
+        (0b10, 2),
+        (0x1004 >> 1, 31),
+        (0, 1),
+        (0, 1),
+        (0, 5),
+    ]));
+    trace.extend(test_packet(&[
+        (2, 16),
+        (0b11, 2),
+        (0b11, 2),
+        (0, 1),
+        (0, 2),
+        (0, 1),
+    ]));
+    assert!(matches!(
+        crate::reconstruct(&trace, &obj_files, &DecoderConfig::default()),
+        Err(Error::InstructionNotFound { pc: 0x1000 })
+    ));
+}
+
+#[test]
+fn test_decode_interrupt() {
+    let mut text = vec![0u8; 0x300];
+    // c.nop; c.nop; c.nop; c.nop
+    text[0x200..0x208].copy_from_slice(&[0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00]);
+    // handler: c.nop; c.nop
+    text[0x280..0x284].copy_from_slice(&[0x01, 0x00, 0x01, 0x00]);
+    let elf = crate::test_elf(&text, &[]);
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    let mut trace = test_packet(&[
+        (0, 16),
+        (0b11, 2),
+        (0b00, 2),
+        (0, 1),
+        (0, 1),
+        (0x200 >> 1, 31),
+        (0, 3),
+    ]);
+    // machine timer interrupt at 0x204
+    trace.extend(test_packet(&[
+        (1, 16),
+        (0b11, 2),
+        (0b01, 2),
+        (0, 1),
+        (1, 1),
+        (7, 5),
+        (1, 1),
+        (0x280 >> 1, 31),
+        (0x204, 32),
+        (0, 6),
+    ]));
+    trace.extend(test_packet(&[
+        (2, 16),
+        (0b10, 2),
+        (0x282 >> 1, 31),
+        (0, 1),
+        (0, 1),
+        (0, 5),
+    ]));
+    trace.extend(test_packet(&[
+        (3, 16),
+        (0b11, 2),
+        (0b11, 2),
+        (0, 1),
+        (0, 2),
+        (0, 1),
+    ]));
+
+    let mut decoder = Decoder::new(&obj_files);
+    let mut decoded: Vec<Decoded> = decoder.push(&trace).unwrap().collect();
+    decoded.extend(decoder.finish().unwrap());
+
+    let mut traps = Vec::new();
+    let mut execution_path = Vec::new();
+    for decoded in decoded {
+        match decoded {
+            Decoded::Event(TraceEvent::Instruction { pc, .. }) => execution_path.push(pc),
+            Decoded::Event(TraceEvent::Trap(trap)) => traps.push(trap),
+            Decoded::Event(_) => (),
+            Decoded::Packet(_) => (),
+        }
+    }
+
+    assert_eq!(execution_path, [0x200, 0x202, 0x280, 0x282]);
+    assert_eq!(
+        traps,
+        [Trap {
+            cause: 7,
+            interrupt: true,
+            epc: 0x204,
+            handler: 0x280,
+        }]
+    );
+}
+
+#[test]
+fn test_decode_exception() {
+    let mut text = vec![0u8; 0x300];
+    // loop: c.nop; c.bnez a0, loop; c.nop; ecall; c.nop
+    text[0x200..0x20c].copy_from_slice(&[
+        0x01, 0x00, 0x7d, 0xfd, 0x01, 0x00, 0x73, 0x00, 0x00, 0x00, 0x01, 0x00,
+    ]);
+    // handler: c.bnez a0, 0x286; c.nop; c.nop; c.nop
+    text[0x280..0x288].copy_from_slice(&[0x19, 0xe1, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00]);
+    let elf = crate::test_elf(&text, &[]);
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    let mut trace = test_packet(&[
+        (0, 16),
+        (0b11, 2),
+        (0b00, 2),
+        (0, 1),
+        (0, 1),
+        (0x200 >> 1, 31),
+        (0, 3),
+    ]);
+    // the loop branch taken and not taken, reported with the last instruction before the trap
+    trace.extend(test_packet(&[
+        (1, 16),
+        (0b01, 2),
+        (2, 5),
+        (0b010, 3),
+        (0x204 >> 1, 31),
+        (0, 1),
+        (0, 1),
+        (0, 5),
+    ]));
+    // environment call from M-mode at 0x206, the branch at the handler is taken
+    trace.extend(test_packet(&[
+        (2, 16),
+        (0b11, 2),
+        (0b01, 2),
+        (0, 1),
+        (1, 1),
+        (11, 5),
+        (0, 1),
+        (0x280 >> 1, 31),
+        (0x206, 32),
+        (0, 6),
+    ]));
+    trace.extend(test_packet(&[
+        (3, 16),
+        (0b10, 2),
+        (0x286 >> 1, 31),
+        (0, 1),
+        (0, 1),
+        (0, 5),
+    ]));
+    trace.extend(test_packet(&[
+        (4, 16),
+        (0b11, 2),
+        (0b11, 2),
+        (0, 1),
+        (0, 2),
+        (0, 1),
+    ]));
+
+    let events = crate::decode(&trace, &obj_files, &DecoderConfig::default()).unwrap();
+    let execution_path: Vec<u32> = events
+        .iter()
+        .filter_map(|event| match event {
+            TraceEvent::Instruction { pc, .. } => Some(*pc),
+            _ => None,
+        })
+        .collect();
+    let traps: Vec<Trap> = events
+        .iter()
+        .filter_map(|event| match event {
+            TraceEvent::Trap(trap) => Some(*trap),
+            _ => None,
+        })
+        .collect();
+
+    // the faulting instruction shows up once, before the handler
+    assert_eq!(
+        execution_path,
+        [0x200, 0x202, 0x200, 0x202, 0x204, 0x206, 0x280, 0x286]
+    );
+    assert_eq!(
+        traps,
+        [Trap {
+            cause: 11,
+            interrupt: false,
+            epc: 0x206,
+            handler: 0x280,
+        }]
+    );
+}
+
+#[test]
+fn test_decode_events() {
+    let (elf, trace) = test_trace();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    let events = crate::decode(&trace, &obj_files, &DecoderConfig::default()).unwrap();
+    assert_eq!(
+        events,
+        [
+            TraceEvent::Resync { pc: 0x100 },
+            TraceEvent::Instruction {
+                pc: 0x100,
+                insn: 0x0001
+            },
+            TraceEvent::Instruction {
+                pc: 0x102,
+                insn: 0xfd7d
+            },
+            TraceEvent::BranchTaken {
+                from: 0x102,
+                to: 0x100
+            },
+            TraceEvent::Instruction {
+                pc: 0x100,
+                insn: 0x0001
+            },
+            TraceEvent::Instruction {
+                pc: 0x102,
+                insn: 0xfd7d
+            },
+            TraceEvent::BranchTaken {
+                from: 0x102,
+                to: 0x100
+            },
+            TraceEvent::Instruction {
+                pc: 0x100,
+                insn: 0x0001
+            },
+            TraceEvent::Instruction {
+                pc: 0x102,
+                insn: 0xfd7d
+            },
+            TraceEvent::BranchNotTaken { pc: 0x102 },
+            TraceEvent::Instruction {
+                pc: 0x104,
+                insn: 0x8082
+            },
+            TraceEvent::Return {
+                from: 0x104,
+                to: 0x200
+            },
+            TraceEvent::Instruction {
+                pc: 0x200,
+                insn: 0x0001
+            },
+            TraceEvent::Instruction {
+                pc: 0x202,
+                insn: 0x0001
+            },
+            TraceEvent::Instruction {
+                pc: 0x204,
+                insn: 0x0001
+            },
+            TraceEvent::TraceStop,
+        ]
+    );
+}
+
+#[test]
+fn test_decode_wrapped_buffer() {
+    let (elf, trace) = test_trace();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    let mut data = vec![0x00, 0x55, 0x0a];
+    data.extend(&trace);
+
+    let events = crate::decode(&data, &obj_files, &DecoderConfig::default()).unwrap();
+    assert_eq!(events[0], TraceEvent::Discarded { bytes: 3 });
+    assert_eq!(events[1], TraceEvent::Resync { pc: 0x100 });
+
+    let execution_path = crate::reconstruct(&data, &obj_files, &DecoderConfig::default()).unwrap();
+    assert_eq!(execution_path, TEST_TRACE_PATH);
+}
+
+#[test]
+fn test_decode_gap() {
+    let (elf, trace) = test_trace();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+    let support = |index| test_packet(&[(index, 16), (0b11, 2), (0b11, 2), (1, 1), (0, 2), (0, 1)]);
+
+    // the packet after the support packet got lost, a later sync packet restarts the
+    // reconstruction
+    let mut data = trace[..8].to_vec();
+    data.extend(support(1));
+    data.extend(test_packet(&[
+        (3, 16),
+        (0b11, 2),
+        (0b00, 2),
+        (0, 1),
+        (0, 1),
+        (0x200 >> 1, 31),
+        (0, 3),
+    ]));
+    data.extend(test_packet(&[
+        (4, 16),
+        (0b10, 2),
+        (0x204 >> 1, 31),
+        (0, 1),
+        (0, 1),
+        (0, 5),
+    ]));
+    data.extend(support(5));
+
+    let events = crate::decode(&data, &obj_files, &DecoderConfig::default()).unwrap();
+    assert!(events.contains(&TraceEvent::Gap {
+        expected: 2,
+        index: 3
+    }));
+    assert!(events.contains(&TraceEvent::Resync { pc: 0x200 }));
+
+    let execution_path = crate::reconstruct(&data, &obj_files, &DecoderConfig::default()).unwrap();
+    assert_eq!(execution_path, [0x100, 0x102, 0x200, 0x202, 0x204]);
+
+    // without a sync packet after the gap everything up to the gap is still decoded
+    let mut data = trace[..8].to_vec();
+    data.extend(support(1));
+    data.extend(&trace[17..25]);
+    data[13] = 3;
+    data.extend(support(4));
+
+    let events = crate::decode(&data, &obj_files, &DecoderConfig::default()).unwrap();
+    assert_eq!(
+        events[..3],
+        [
+            TraceEvent::Resync { pc: 0x100 },
+            TraceEvent::Instruction {
+                pc: 0x100,
+                insn: 0x0001
+            },
+            TraceEvent::Instruction {
+                pc: 0x102,
+                insn: 0xfd7d
+            },
+        ]
+    );
+    assert!(events.contains(&TraceEvent::Gap {
+        expected: 2,
+        index: 3
+    }));
+}
+
+#[test]
+fn test_decode_jump_target_index() {
+    use crate::trace_decoder::{BranchCount, JumpTargetIndex};
+
+    let elf = crate::simulator::test_elf();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+    let config = DecoderConfig {
+        format0_extensions: true,
+        jump_target_cache_size: 4,
+        ..Default::default()
+    };
+
+    // the second return to 0x108 refers to the cached target of the first one
+    let path = crate::simulator::test_path(0, &[0, 0]);
+    let mut packets = crate::simulator::simulate(&obj_files, &path, &config).unwrap();
+    let Packet::AddressBranchMap(index, map) = packets[2] else {
+        panic!("unexpected packet {:?}", packets[2]);
+    };
+    assert_eq!(map.address, 0x108);
+    packets[2] = Packet::JumpTargetIndex(
+        index,
+        JumpTargetIndex {
+            cache_index: (0x108 >> 1) & 0xf,
+            branches: map.branches,
+            branch_map: map.branch_map,
+            notify: false,
+            updiscon: false,
+        },
+    );
+
+    let trace = crate::encode_packets(&packets, &config).unwrap();
+    assert_eq!(
+        crate::reconstruct(&trace, &obj_files, &config).unwrap(),
+        path
+    );
+
+    packets[2] = Packet::BranchCount(
+        index,
+        BranchCount {
+            branch_count: 2,
+            address: None,
+            notify: false,
+            updiscon: false,
+        },
+    );
+    let trace = crate::encode_packets(&packets, &config).unwrap();
+    assert!(matches!(
+        crate::reconstruct(&trace, &obj_files, &config),
+        Err(Error::UnsupportedPacket { packet_index: 2 })
+    ));
+}
+
+#[cfg(test)]
+fn test_events(packets: &[Packet]) -> Result<Vec<TraceEvent>, Error> {
+    let (elf, _) = test_trace();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+    let config = DecoderConfig::default();
+
+    let trace = crate::encode_packets(packets, &config).unwrap();
+    crate::decode(&trace, &obj_files, &config)
+}
+
+#[test]
+fn test_decode_notify() {
+    use crate::trace_decoder::{Address, Sync};
+
+    // a notification in the loop and one in front of the last instruction
+    let packets = [
+        Packet::Sync(
+            0,
+            Sync {
+                branch: true,
+                privilege: false,
+                address: 0x100,
+            },
+        ),
+        Packet::AddressBranchMap(
+            1,
+            AddressBranchMap {
+                address: 0x100,
+                branches: 1,
+                branch_map: 0,
+                notify: true,
+                updiscon: false,
+            },
+        ),
+        Packet::AddressBranchMap(
+            2,
+            AddressBranchMap {
+                address: 0x200,
+                branches: 2,
+                branch_map: 0b10,
+                notify: false,
+                updiscon: false,
+            },
+        ),
+        Packet::Address(
+            3,
+            Address {
+                address: 0x202,
+                notify: true,
+                updiscon: false,
+            },
+        ),
+        Packet::Address(
+            4,
+            Address {
+                address: 0x204,
+                notify: false,
+                updiscon: false,
+            },
+        ),
+        Packet::Support(
+            5,
+            Support {
+                enable: false,
+                qual_status: 0,
+            },
+        ),
+    ];
+
+    let events = test_events(&packets).unwrap();
+    let execution: Vec<(u32, bool)> = events
+        .iter()
+        .filter_map(|event| match *event {
+            TraceEvent::Instruction { pc, .. } => Some((pc, false)),
+            TraceEvent::Notify { pc } => Some((pc, true)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        execution,
+        [
+            (0x100, false),
+            (0x102, false),
+            (0x100, false),
+            (0x100, true),
+            (0x102, false),
+            (0x100, false),
+            (0x102, false),
+            (0x104, false),
+            (0x200, false),
+            (0x202, false),
+            (0x202, true),
+            (0x204, false),
+        ]
+    );
+    assert_eq!(events.last(), Some(&TraceEvent::TraceStop));
+}
+
+#[test]
+fn test_decode_updiscon() {
+    use crate::trace_decoder::{Address, Sync};
+
+    // the return target is the last instruction before the resync, the code following it
+    // isn't executed
+    let mut packets = vec![
+        Packet::Sync(
+            0,
+            Sync {
+                branch: true,
+                privilege: false,
+                address: 0x100,
+            },
+        ),
+        Packet::AddressBranchMap(
+            1,
+            AddressBranchMap {
+                address: 0x200,
+                branches: 3,
+                branch_map: 0b100,
+                notify: false,
+                updiscon: true,
+            },
+        ),
+        Packet::Sync(
+            2,
+            Sync {
+                branch: true,
+                privilege: true,
+                address: 0x202,
+            },
+        ),
+        Packet::Address(
+            3,
+            Address {
+                address: 0x204,
+                notify: false,
+                updiscon: false,
+            },
+        ),
+        Packet::Support(
+            4,
+            Support {
+                enable: false,
+                qual_status: 0b11,
+            },
+        ),
+    ];
+
+    let events = test_events(&packets).unwrap();
+    let position = |event| events.iter().position(|e| *e == event).unwrap();
+    assert_eq!(
+        position(TraceEvent::Resync { pc: 0x202 }),
+        position(TraceEvent::Instruction {
+            pc: 0x200,
+            insn: 0x0001
+        }) + 1
+    );
+    assert_eq!(events.last(), Some(&TraceEvent::TraceStop));
+
+    // without updiscon the code after the return target is followed up to the illegal
+    // instruction behind it
+    if let Packet::AddressBranchMap(_, map) = &mut packets[1] {
+        map.updiscon = false;
+    }
+    assert!(matches!(
+        test_events(&packets),
+        Err(Error::IllegalInstruction { pc: 0x206, .. })
+    ));
+}
+
+#[test]
+fn test_decode_trace_lost() {
+    use crate::trace_decoder::{Address, Sync};
+
+    let packets = [
+        Packet::Sync(
+            0,
+            Sync {
+                branch: true,
+                privilege: false,
+                address: 0x100,
+            },
+        ),
+        // the packet with the outcome of the loop's branch got lost
+        Packet::Support(
+            1,
+            Support {
+                enable: true,
+                qual_status: 0b10,
+            },
+        ),
+        Packet::Sync(
+            2,
+            Sync {
+                branch: true,
+                privilege: false,
+                address: 0x200,
+            },
+        ),
+        Packet::Address(
+            3,
+            Address {
+                address: 0x204,
+                notify: false,
+                updiscon: false,
+            },
+        ),
+        Packet::Support(
+            4,
+            Support {
+                enable: false,
+                qual_status: 0b01,
+            },
+        ),
+    ];
+
+    let events = test_events(&packets).unwrap();
+    assert_eq!(
+        events,
+        [
+            TraceEvent::Resync { pc: 0x100 },
+            TraceEvent::Instruction {
+                pc: 0x100,
+                insn: 0x0001
+            },
+            TraceEvent::Instruction {
+                pc: 0x102,
+                insn: 0xfd7d
+            },
+            TraceEvent::TraceLost,
+            TraceEvent::Resync { pc: 0x200 },
+            TraceEvent::Instruction {
+                pc: 0x200,
+                insn: 0x0001
+            },
+            TraceEvent::Instruction {
+                pc: 0x202,
+                insn: 0x0001
+            },
+            TraceEvent::Instruction {
+                pc: 0x204,
+                insn: 0x0001
+            },
+            TraceEvent::TraceStop,
+        ]
+    );
+}
@@ -0,0 +1,605 @@
+// bit patterns are grouped by instruction fields rather than by nibbles
+#![allow(clippy::unusual_byte_groupings)]
+
+const REGISTERS: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// A disassembled RV32IMAC (+Zicsr, Zifencei) instruction
+///
+/// Aliases like `li`, `mv` or `ret` are used where they apply and compressed instructions are
+/// shown as their 32 bit equivalent, like `objdump -d` does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    pub mnemonic: String,
+    /// Comma separated operands, empty if the instruction doesn't have any
+    pub operands: String,
+    /// The target of a direct jump or branch
+    pub target: Option<u32>,
+}
+
+impl std::fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.operands.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operands)
+        }
+    }
+}
+
+/// Disassemble the instruction at `pc`, 16 bit instructions are zero-extended
+pub fn disassemble(insn: u32, pc: u32) -> Disassembly {
+    let res = if insn & 0b11 == 0b11 {
+        disassemble_32(insn, pc)
+    } else {
+        disassemble_16(insn as u16, pc)
+    };
+
+    res.unwrap_or_else(|| Disassembly {
+        mnemonic: "unknown".to_string(),
+        operands: String::new(),
+        target: None,
+    })
+}
+
+fn op(mnemonic: &str, operands: String) -> Option<Disassembly> {
+    Some(Disassembly {
+        mnemonic: mnemonic.to_string(),
+        operands,
+        target: None,
+    })
+}
+
+fn jump(mnemonic: &str, operands: String, target: u32) -> Option<Disassembly> {
+    Some(Disassembly {
+        mnemonic: mnemonic.to_string(),
+        operands: if operands.is_empty() {
+            format!("{:#x}", target)
+        } else {
+            format!("{}, {:#x}", operands, target)
+        },
+        target: Some(target),
+    })
+}
+
+fn reg(index: u32) -> &'static str {
+    REGISTERS[index as usize & 0b11111]
+}
+
+/// Register of the 3 bit register fields of compressed instructions
+fn creg(index: u16) -> &'static str {
+    REGISTERS[8 + (index as usize & 0b111)]
+}
+
+/// Sign extend the lowest `bits` bits of `value`
+fn sext(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+fn bit(value: u32, bit: u32) -> u32 {
+    (value >> bit) & 1
+}
+
+fn bits(value: u32, high: u32, low: u32) -> u32 {
+    (value >> low) & (u32::MAX >> (31 - (high - low)))
+}
+
+fn disassemble_32(insn: u32, pc: u32) -> Option<Disassembly> {
+    let rd = bits(insn, 11, 7);
+    let rs1 = bits(insn, 19, 15);
+    let rs2 = bits(insn, 24, 20);
+    let funct3 = bits(insn, 14, 12);
+    let funct7 = bits(insn, 31, 25);
+    let imm_i = sext(bits(insn, 31, 20), 12);
+    let imm_s = sext(bits(insn, 31, 25) << 5 | bits(insn, 11, 7), 12);
+
+    match insn & 0b11111_11 {
+        0b01101_11 => op("lui", format!("{}, {:#x}", reg(rd), insn >> 12)),
+        0b00101_11 => op("auipc", format!("{}, {:#x}", reg(rd), insn >> 12)),
+        0b11011_11 => {
+            let offset = bit(insn, 31) << 20
+                | bits(insn, 19, 12) << 12
+                | bit(insn, 20) << 11
+                | bits(insn, 30, 21) << 1;
+            let target = pc.wrapping_add(sext(offset, 21) as u32);
+            match rd {
+                0 => jump("j", String::new(), target),
+                1 => jump("jal", String::new(), target),
+                rd => jump("jal", reg(rd).to_string(), target),
+            }
+        }
+        0b11001_11 if funct3 == 0 => match (rd, rs1, imm_i) {
+            (0, 1, 0) => op("ret", String::new()),
+            (0, rs1, 0) => op("jr", reg(rs1).to_string()),
+            (0, rs1, imm) => op("jr", format!("{}({})", imm, reg(rs1))),
+            (1, rs1, 0) => op("jalr", reg(rs1).to_string()),
+            (1, rs1, imm) => op("jalr", format!("{}({})", imm, reg(rs1))),
+            (rd, rs1, imm) => op("jalr", format!("{}, {}({})", reg(rd), imm, reg(rs1))),
+        },
+        0b11000_11 => {
+            let offset = bit(insn, 31) << 12
+                | bit(insn, 7) << 11
+                | bits(insn, 30, 25) << 5
+                | bits(insn, 11, 8) << 1;
+            let target = pc.wrapping_add(sext(offset, 13) as u32);
+            let mnemonic = match funct3 {
+                0b000 => "beq",
+                0b001 => "bne",
+                0b100 => "blt",
+                0b101 => "bge",
+                0b110 => "bltu",
+                0b111 => "bgeu",
+                _ => return None,
+            };
+            match (mnemonic, rs1, rs2) {
+                ("beq", rs1, 0) => jump("beqz", reg(rs1).to_string(), target),
+                ("bne", rs1, 0) => jump("bnez", reg(rs1).to_string(), target),
+                ("blt", rs1, 0) => jump("bltz", reg(rs1).to_string(), target),
+                ("bge", rs1, 0) => jump("bgez", reg(rs1).to_string(), target),
+                ("blt", 0, rs2) => jump("bgtz", reg(rs2).to_string(), target),
+                ("bge", 0, rs2) => jump("blez", reg(rs2).to_string(), target),
+                (mnemonic, rs1, rs2) => {
+                    jump(mnemonic, format!("{}, {}", reg(rs1), reg(rs2)), target)
+                }
+            }
+        }
+        0b00000_11 => {
+            let mnemonic = match funct3 {
+                0b000 => "lb",
+                0b001 => "lh",
+                0b010 => "lw",
+                0b100 => "lbu",
+                0b101 => "lhu",
+                _ => return None,
+            };
+            op(mnemonic, format!("{}, {}({})", reg(rd), imm_i, reg(rs1)))
+        }
+        0b01000_11 => {
+            let mnemonic = match funct3 {
+                0b000 => "sb",
+                0b001 => "sh",
+                0b010 => "sw",
+                _ => return None,
+            };
+            op(mnemonic, format!("{}, {}({})", reg(rs2), imm_s, reg(rs1)))
+        }
+        0b00100_11 => {
+            let shamt = rs2;
+            match (funct3, funct7) {
+                (0b000, _) => match (rd, rs1, imm_i) {
+                    (0, 0, 0) => op("nop", String::new()),
+                    (rd, 0, imm) => op("li", format!("{}, {}", reg(rd), imm)),
+                    (rd, rs1, 0) => op("mv", format!("{}, {}", reg(rd), reg(rs1))),
+                    (rd, rs1, imm) => op("addi", format!("{}, {}, {}", reg(rd), reg(rs1), imm)),
+                },
+                (0b011, _) if imm_i == 1 => op("seqz", format!("{}, {}", reg(rd), reg(rs1))),
+                (0b100, _) if imm_i == -1 => op("not", format!("{}, {}", reg(rd), reg(rs1))),
+                (0b010 | 0b011 | 0b100 | 0b110 | 0b111, _) => {
+                    let mnemonic = match funct3 {
+                        0b010 => "slti",
+                        0b011 => "sltiu",
+                        0b100 => "xori",
+                        0b110 => "ori",
+                        _ => "andi",
+                    };
+                    op(mnemonic, format!("{}, {}, {}", reg(rd), reg(rs1), imm_i))
+                }
+                (0b001, 0b0000000) => op("slli", format!("{}, {}, {}", reg(rd), reg(rs1), shamt)),
+                (0b101, 0b0000000) => op("srli", format!("{}, {}, {}", reg(rd), reg(rs1), shamt)),
+                (0b101, 0b0100000) => op("srai", format!("{}, {}, {}", reg(rd), reg(rs1), shamt)),
+                _ => None,
+            }
+        }
+        0b01100_11 => {
+            let mnemonic = match (funct7, funct3) {
+                (0b0000000, 0b000) => "add",
+                (0b0100000, 0b000) => "sub",
+                (0b0000000, 0b001) => "sll",
+                (0b0000000, 0b010) => "slt",
+                (0b0000000, 0b011) => "sltu",
+                (0b0000000, 0b100) => "xor",
+                (0b0000000, 0b101) => "srl",
+                (0b0100000, 0b101) => "sra",
+                (0b0000000, 0b110) => "or",
+                (0b0000000, 0b111) => "and",
+                (0b0000001, 0b000) => "mul",
+                (0b0000001, 0b001) => "mulh",
+                (0b0000001, 0b010) => "mulhsu",
+                (0b0000001, 0b011) => "mulhu",
+                (0b0000001, 0b100) => "div",
+                (0b0000001, 0b101) => "divu",
+                (0b0000001, 0b110) => "rem",
+                (0b0000001, 0b111) => "remu",
+                _ => return None,
+            };
+            match (mnemonic, rs1, rs2) {
+                ("sub", 0, rs2) => op("neg", format!("{}, {}", reg(rd), reg(rs2))),
+                ("sltu", 0, rs2) => op("snez", format!("{}, {}", reg(rd), reg(rs2))),
+                ("slt", rs1, 0) => op("sltz", format!("{}, {}", reg(rd), reg(rs1))),
+                ("slt", 0, rs2) => op("sgtz", format!("{}, {}", reg(rd), reg(rs2))),
+                (mnemonic, rs1, rs2) => {
+                    op(mnemonic, format!("{}, {}, {}", reg(rd), reg(rs1), reg(rs2)))
+                }
+            }
+        }
+        0b00011_11 if rd == 0 && rs1 == 0 => match funct3 {
+            0b000 => {
+                let fm = bits(insn, 31, 28);
+                let pred = bits(insn, 27, 24);
+                let succ = bits(insn, 23, 20);
+                match (fm, pred, succ) {
+                    (0b1000, 0b0011, 0b0011) => op("fence.tso", String::new()),
+                    (0b0000, 0b1111, 0b1111) => op("fence", String::new()),
+                    (0b0000, pred, succ) => {
+                        op("fence", format!("{}, {}", fence_set(pred), fence_set(succ)))
+                    }
+                    _ => None,
+                }
+            }
+            0b001 if imm_i == 0 => op("fence.i", String::new()),
+            _ => None,
+        },
+        0b11100_11 => disassemble_system(insn),
+        0b01011_11 if funct3 == 0b010 => {
+            let funct5 = bits(insn, 31, 27);
+            let mnemonic = match funct5 {
+                0b00010 if rs2 == 0 => {
+                    return op(
+                        &format!("lr.w{}", ordering(insn)),
+                        format!("{}, ({})", reg(rd), reg(rs1)),
+                    )
+                }
+                0b00011 => "sc.w",
+                0b00001 => "amoswap.w",
+                0b00000 => "amoadd.w",
+                0b00100 => "amoxor.w",
+                0b01100 => "amoand.w",
+                0b01000 => "amoor.w",
+                0b10000 => "amomin.w",
+                0b10100 => "amomax.w",
+                0b11000 => "amominu.w",
+                0b11100 => "amomaxu.w",
+                _ => return None,
+            };
+            op(
+                &format!("{}{}", mnemonic, ordering(insn)),
+                format!("{}, {}, ({})", reg(rd), reg(rs2), reg(rs1)),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// The `.aq`/`.rl` suffix of atomic instructions
+fn ordering(insn: u32) -> &'static str {
+    match (bit(insn, 26), bit(insn, 25)) {
+        (0, 0) => "",
+        (1, 0) => ".aq",
+        (0, 1) => ".rl",
+        _ => ".aqrl",
+    }
+}
+
+fn fence_set(set: u32) -> String {
+    if set == 0 {
+        return "0".to_string();
+    }
+
+    "iorw"
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| set & (0b1000 >> i) != 0)
+        .map(|(_, c)| c)
+        .collect()
+}
+
+fn disassemble_system(insn: u32) -> Option<Disassembly> {
+    let rd = bits(insn, 11, 7);
+    let rs1 = bits(insn, 19, 15);
+    let funct3 = bits(insn, 14, 12);
+    let csr = bits(insn, 31, 20);
+
+    if funct3 == 0 {
+        return match insn {
+            0b000000000000_00000_000_00000_1110011 => op("ecall", String::new()),
+            0b000000000001_00000_000_00000_1110011 => op("ebreak", String::new()),
+            0b000100000010_00000_000_00000_1110011 => op("sret", String::new()),
+            0b001100000010_00000_000_00000_1110011 => op("mret", String::new()),
+            0b000100000101_00000_000_00000_1110011 => op("wfi", String::new()),
+            _ => None,
+        };
+    }
+
+    let name = csr_name(csr);
+    // the immediate forms use the rs1 field as an unsigned immediate
+    let source = if funct3 & 0b100 != 0 {
+        rs1.to_string()
+    } else {
+        reg(rs1).to_string()
+    };
+
+    let mnemonic = match funct3 {
+        0b001 => "csrrw",
+        0b010 => "csrrs",
+        0b011 => "csrrc",
+        0b101 => "csrrwi",
+        0b110 => "csrrsi",
+        0b111 => "csrrci",
+        _ => return None,
+    };
+
+    match (mnemonic, rd, rs1) {
+        ("csrrs", rd, 0) => match csr {
+            0xc00 => op("rdcycle", reg(rd).to_string()),
+            0xc01 => op("rdtime", reg(rd).to_string()),
+            0xc02 => op("rdinstret", reg(rd).to_string()),
+            0xc80 => op("rdcycleh", reg(rd).to_string()),
+            0xc81 => op("rdtimeh", reg(rd).to_string()),
+            0xc82 => op("rdinstreth", reg(rd).to_string()),
+            _ => op("csrr", format!("{}, {}", reg(rd), name)),
+        },
+        ("csrrw", 0, _) => op("csrw", format!("{}, {}", name, source)),
+        ("csrrs", 0, _) => op("csrs", format!("{}, {}", name, source)),
+        ("csrrc", 0, _) => op("csrc", format!("{}, {}", name, source)),
+        ("csrrwi", 0, _) => op("csrwi", format!("{}, {}", name, source)),
+        ("csrrsi", 0, _) => op("csrsi", format!("{}, {}", name, source)),
+        ("csrrci", 0, _) => op("csrci", format!("{}, {}", name, source)),
+        (mnemonic, rd, _) => op(mnemonic, format!("{}, {}, {}", reg(rd), name, source)),
+    }
+}
+
+fn csr_name(csr: u32) -> String {
+    let name = match csr {
+        0x001 => "fflags",
+        0x002 => "frm",
+        0x003 => "fcsr",
+        0xc00 => "cycle",
+        0xc01 => "time",
+        0xc02 => "instret",
+        0xc80 => "cycleh",
+        0xc81 => "timeh",
+        0xc82 => "instreth",
+        0xf11 => "mvendorid",
+        0xf12 => "marchid",
+        0xf13 => "mimpid",
+        0xf14 => "mhartid",
+        0x300 => "mstatus",
+        0x301 => "misa",
+        0x302 => "medeleg",
+        0x303 => "mideleg",
+        0x304 => "mie",
+        0x305 => "mtvec",
+        0x306 => "mcounteren",
+        0x310 => "mstatush",
+        0x320 => "mcountinhibit",
+        0x340 => "mscratch",
+        0x341 => "mepc",
+        0x342 => "mcause",
+        0x343 => "mtval",
+        0x344 => "mip",
+        0xb00 => "mcycle",
+        0xb02 => "minstret",
+        0xb80 => "mcycleh",
+        0xb82 => "minstreth",
+        0x3a0 => "pmpcfg0",
+        0x3a1 => "pmpcfg1",
+        0x3a2 => "pmpcfg2",
+        0x3a3 => "pmpcfg3",
+        0x3b0..=0x3bf => return format!("pmpaddr{}", csr - 0x3b0),
+        0x7a0 => "tselect",
+        0x7a1 => "tdata1",
+        0x7a2 => "tdata2",
+        0x7a3 => "tdata3",
+        0x7b0 => "dcsr",
+        0x7b1 => "dpc",
+        0x7b2 => "dscratch0",
+        0x7b3 => "dscratch1",
+        csr => return format!("{:#x}", csr),
+    };
+    name.to_string()
+}
+
+fn disassemble_16(insn: u16, pc: u32) -> Option<Disassembly> {
+    let word = insn as u32;
+    let funct3 = bits(word, 15, 13);
+    let rd = bits(word, 11, 7);
+    let rs2 = bits(word, 6, 2);
+    let rd_c = creg(bits(word, 4, 2) as u16);
+    let rs1_c = creg(bits(word, 9, 7) as u16);
+    let imm6 = sext(bit(word, 12) << 5 | bits(word, 6, 2), 6);
+    let shamt = bit(word, 12) << 5 | bits(word, 6, 2);
+
+    match (word & 0b11, funct3) {
+        (0b00, 0b000) => {
+            if insn == 0 {
+                return op("unimp", String::new());
+            }
+            let imm = bits(word, 10, 7) << 6
+                | bits(word, 12, 11) << 4
+                | bit(word, 5) << 3
+                | bit(word, 6) << 2;
+            if imm == 0 {
+                return None;
+            }
+            op("addi", format!("{}, sp, {}", rd_c, imm))
+        }
+        (0b00, 0b010 | 0b110) => {
+            let imm = bit(word, 5) << 6 | bits(word, 12, 10) << 3 | bit(word, 6) << 2;
+            if funct3 == 0b010 {
+                op("lw", format!("{}, {}({})", rd_c, imm, rs1_c))
+            } else {
+                op("sw", format!("{}, {}({})", rd_c, imm, rs1_c))
+            }
+        }
+        (0b01, 0b000) => match (rd, imm6) {
+            (0, 0) => op("nop", String::new()),
+            (0, imm) => op("c.nop", imm.to_string()),
+            (rd, 0) => op("c.addi", format!("{}, 0", reg(rd))),
+            (rd, imm) => op("addi", format!("{}, {}, {}", reg(rd), reg(rd), imm)),
+        },
+        (0b01, 0b001 | 0b101) => {
+            let offset = bit(word, 12) << 11
+                | bit(word, 8) << 10
+                | bits(word, 10, 9) << 8
+                | bit(word, 6) << 7
+                | bit(word, 7) << 6
+                | bit(word, 2) << 5
+                | bit(word, 11) << 4
+                | bits(word, 5, 3) << 1;
+            let target = pc.wrapping_add(sext(offset, 12) as u32);
+            if funct3 == 0b001 {
+                jump("jal", String::new(), target)
+            } else {
+                jump("j", String::new(), target)
+            }
+        }
+        (0b01, 0b010) => match rd {
+            0 => op("c.li", format!("zero, {}", imm6)),
+            rd => op("li", format!("{}, {}", reg(rd), imm6)),
+        },
+        (0b01, 0b011) => {
+            if rd == 2 {
+                let imm = bit(word, 12) << 9
+                    | bits(word, 4, 3) << 7
+                    | bit(word, 5) << 6
+                    | bit(word, 2) << 5
+                    | bit(word, 6) << 4;
+                if imm == 0 {
+                    return None;
+                }
+                op("addi", format!("sp, sp, {}", sext(imm, 10)))
+            } else if imm6 == 0 {
+                None
+            } else if rd == 0 {
+                op("c.lui", format!("zero, {:#x}", imm6 as u32 & 0xfffff))
+            } else {
+                op("lui", format!("{}, {:#x}", reg(rd), imm6 as u32 & 0xfffff))
+            }
+        }
+        (0b01, 0b100) => {
+            let rd_c = rs1_c;
+            match bits(word, 11, 10) {
+                0b00 | 0b01 if bit(word, 12) != 0 => None,
+                0b00 if shamt == 0 => op("c.srli64", rd_c.to_string()),
+                0b01 if shamt == 0 => op("c.srai64", rd_c.to_string()),
+                0b00 => op("srli", format!("{}, {}, {}", rd_c, rd_c, shamt)),
+                0b01 => op("srai", format!("{}, {}, {}", rd_c, rd_c, shamt)),
+                0b10 => op("andi", format!("{}, {}, {}", rd_c, rd_c, imm6)),
+                _ => {
+                    let rs2_c = creg(bits(word, 4, 2) as u16);
+                    let mnemonic = match (bit(word, 12), bits(word, 6, 5)) {
+                        (0, 0b00) => "sub",
+                        (0, 0b01) => "xor",
+                        (0, 0b10) => "or",
+                        (0, 0b11) => "and",
+                        _ => return None,
+                    };
+                    op(mnemonic, format!("{}, {}, {}", rd_c, rd_c, rs2_c))
+                }
+            }
+        }
+        (0b01, 0b110 | 0b111) => {
+            let offset = bit(word, 12) << 8
+                | bits(word, 6, 5) << 6
+                | bit(word, 2) << 5
+                | bits(word, 11, 10) << 3
+                | bits(word, 4, 3) << 1;
+            let target = pc.wrapping_add(sext(offset, 9) as u32);
+            if funct3 == 0b110 {
+                jump("beqz", rs1_c.to_string(), target)
+            } else {
+                jump("bnez", rs1_c.to_string(), target)
+            }
+        }
+        (0b10, 0b000) => match (rd, shamt) {
+            (_, 32..) => None,
+            (rd, 0) => op("c.slli64", reg(rd).to_string()),
+            (0, shamt) => op("c.slli", format!("zero, {}", shamt)),
+            (rd, shamt) => op("slli", format!("{}, {}, {}", reg(rd), reg(rd), shamt)),
+        },
+        (0b10, 0b010) => {
+            let imm = bits(word, 3, 2) << 6 | bit(word, 12) << 5 | bits(word, 6, 4) << 2;
+            if rd == 0 {
+                return None;
+            }
+            op("lw", format!("{}, {}(sp)", reg(rd), imm))
+        }
+        (0b10, 0b100) => match (bit(word, 12), rd, rs2) {
+            (0, 0, 0) => None,
+            (0, 1, 0) => op("ret", String::new()),
+            (0, rs1, 0) => op("jr", reg(rs1).to_string()),
+            (0, 0, rs2) => op("c.mv", format!("zero, {}", reg(rs2))),
+            (0, rd, rs2) => op("mv", format!("{}, {}", reg(rd), reg(rs2))),
+            (_, 0, 0) => op("ebreak", String::new()),
+            (_, rs1, 0) => op("jalr", reg(rs1).to_string()),
+            (_, 0, rs2) => op("c.add", format!("zero, {}", reg(rs2))),
+            (_, rd, rs2) => op("add", format!("{}, {}, {}", reg(rd), reg(rd), reg(rs2))),
+        },
+        (0b10, 0b110) => {
+            let imm = bits(word, 8, 7) << 6 | bits(word, 12, 9) << 2;
+            op("sw", format!("{}, {}(sp)", reg(rs2), imm))
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_disassemble() {
+    let cases: &[(u32, u32, &str)] = &[
+        (0x0505, 0x0, "addi a0, a0, 1"),
+        (0xfeb50ee3, 0x4, "beq a0, a1, 0x0"),
+        (0xfd65, 0x8, "bnez a0, 0x0"),
+        (0x3fdd, 0xa, "jal 0x0"),
+        (0x4532, 0xc, "lw a0, 12(sp)"),
+        (0x30059573, 0x10, "csrrw a0, mstatus, a1"),
+        (0x7c002573, 0x14, "csrr a0, 0x7c0"),
+        (0x0ff0000f, 0x18, "fence"),
+        (0x0000100f, 0x1c, "fence.i"),
+        (0x0310000f, 0x20, "fence rw, w"),
+        (0x06b6252f, 0x24, "amoadd.w.aqrl a0, a1, (a2)"),
+        (0x1005a52f, 0x28, "lr.w a0, (a1)"),
+        (0x02c58533, 0x2c, "mul a0, a1, a2"),
+        (0x12345537, 0x30, "lui a0, 0x12345"),
+        (0x00001517, 0x34, "auipc a0, 0x1"),
+        (0x5575, 0x38, "li a0, -3"),
+        (0x8082, 0x3a, "ret"),
+        (0x30200073, 0x3c, "mret"),
+        (0x10500073, 0x40, "wfi"),
+        (0x00000073, 0x44, "ecall"),
+        (0x852e, 0x48, "mv a0, a1"),
+        (0x7139, 0x4a, "addi sp, sp, -64"),
+        (0x0808, 0x4c, "addi a0, sp, 16"),
+        (0x850d, 0x4e, "srai a0, a0, 3"),
+        (0x657d, 0x50, "lui a0, 0x1f"),
+        (0x757d, 0x0, "lui a0, 0xfffff"),
+        (0xb77d, 0x52, "j 0x0"),
+        (0x004580e7, 0x0, "jalr 4(a1)"),
+        (0x00458567, 0x0, "jalr a0, 4(a1)"),
+        (0x9582, 0x0, "jalr a1"),
+        (0x00b04063, 0x10, "bgtz a1, 0x10"),
+        (0xc0002573, 0x0, "rdcycle a0"),
+        (0x3001d073, 0x0, "csrwi mstatus, 3"),
+        (0x40b00533, 0x0, "neg a0, a1"),
+        (0x0015b513, 0x0, "seqz a0, a1"),
+        (0x8330000f, 0x0, "fence.tso"),
+        (0x0000, 0x0, "unimp"),
+        (0x0015, 0x0, "c.nop 5"),
+        (0xffffffff, 0x0, "unknown"),
+    ];
+
+    for (insn, pc, expected) in cases {
+        assert_eq!(
+            disassemble(*insn, *pc).to_string(),
+            *expected,
+            "{:#x} at {:#x}",
+            insn,
+            pc
+        );
+    }
+
+    assert_eq!(disassemble(0xfeb50ee3, 0x4).target, Some(0x0));
+    assert_eq!(disassemble(0x8082, 0x0).target, None);
+}
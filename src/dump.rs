@@ -0,0 +1,413 @@
+use crate::config::{AddressMode, DecoderConfig};
+use crate::json;
+use crate::trace_decoder::{Packet, PacketParser};
+use crate::Error;
+
+/// A bit field of a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    /// Offset of the first bit in the packet, bit 0 is the LSB of the header byte
+    pub offset: usize,
+    pub width: usize,
+    pub value: u32,
+    /// Whether sign extension bits all repeat the bit in front of them, `None` for other fields
+    pub consistent: Option<bool>,
+    /// Whether a notify or updiscon flag is set, i.e. its bit differs from the bit in front of
+    /// it, `None` for other fields
+    pub set: Option<bool>,
+}
+
+/// A packet together with the raw data it was parsed from
+#[derive(Debug, Clone)]
+pub struct PacketDump {
+    /// Byte offset of the packet in the trace data
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    /// `None` if the format isn't known
+    pub packet: Option<Packet>,
+    pub fields: Vec<Field>,
+    iaddress_lsb: u32,
+    /// Width of the address field if it holds a sign extended difference to the previous
+    /// address
+    difference_bits: Option<usize>,
+}
+
+impl PacketDump {
+    pub(crate) fn new(
+        offset: usize,
+        bytes: Vec<u8>,
+        packet: Option<Packet>,
+        mut fields: Vec<Field>,
+        config: &DecoderConfig,
+    ) -> Self {
+        let bit = |offset: usize| (bytes[offset / 8] >> (offset % 8)) & 1;
+
+        for field in &mut fields {
+            if field.name == "sign_extend" && field.offset != 0 {
+                let expected = bit(field.offset - 1);
+                let consistent = (field.offset..field.offset + field.width)
+                    .all(|offset| bit(offset) == expected);
+                field.consistent = Some(consistent);
+            }
+            if matches!(field.name, "notify" | "updiscon") {
+                field.set = Some(bit(field.offset) != bit(field.offset - 1));
+            }
+        }
+
+        // sync and exception packets always carry the full address
+        let differential = config.address_mode == AddressMode::Differential
+            && matches!(
+                packet,
+                Some(Packet::Address(..) | Packet::AddressBranchMap(..) | Packet::BranchCount(..))
+            );
+
+        Self {
+            offset,
+            bytes,
+            packet,
+            fields,
+            iaddress_lsb: config.iaddress_lsb,
+            difference_bits: differential.then(|| config.address_bits()),
+        }
+    }
+
+    /// The address the packet reports
+    fn address(&self) -> Option<u32> {
+        match self.packet {
+            Some(Packet::Sync(_, sync)) => Some(sync.address),
+            Some(Packet::Exception(_, exception)) => Some(exception.address),
+            Some(Packet::Address(_, address)) => Some(address.address),
+            Some(Packet::AddressBranchMap(_, address)) => Some(address.address),
+            Some(Packet::BranchCount(_, count)) => count.address,
+            _ => None,
+        }
+    }
+
+    /// The address field as the address it stands for
+    fn address_string(&self, field: &Field) -> String {
+        let lsb = self.iaddress_lsb;
+        match (self.difference_bits, self.address()) {
+            (Some(bits), Some(address)) => {
+                let difference = ((field.value << (32 - bits)) as i32 >> (32 - bits)) << lsb;
+                let sign = if difference < 0 { "-" } else { "+" };
+                format!(
+                    "delta {}{:#x} ({:#x} << {}) -> {:#010x}",
+                    sign,
+                    difference.unsigned_abs(),
+                    field.value,
+                    lsb,
+                    address
+                )
+            }
+            _ => format!(
+                "{:#010x} ({:#x} << {})",
+                field.value << lsb,
+                field.value,
+                lsb
+            ),
+        }
+    }
+
+    /// The value of the first field with the given name
+    pub fn field(&self, name: &str) -> Option<u32> {
+        self.fields
+            .iter()
+            .find(|field| field.name == name)
+            .map(|field| field.value)
+    }
+
+    /// Name of the packet type
+    pub fn kind(&self) -> &'static str {
+        match self.packet {
+            Some(Packet::Sync(..)) => "sync",
+            Some(Packet::Exception(..)) => "exception",
+            Some(Packet::Support(..)) => "support",
+            Some(Packet::Address(..)) => "address",
+            Some(Packet::AddressBranchMap(..)) => "branch map with address",
+            Some(Packet::NoAddressBranchMap(..)) => "branch map",
+            Some(Packet::BranchCount(..)) => "branch count",
+            Some(Packet::JumpTargetIndex(..)) => "jump target index",
+            None => "unknown",
+        }
+    }
+
+    /// One JSON object with all fields
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<u32>| match value {
+            Some(value) => value.to_string(),
+            None => "null".to_string(),
+        };
+        let bytes: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| {
+                let consistent = match field.consistent {
+                    Some(consistent) => format!(",\"consistent\":{}", consistent),
+                    None => String::new(),
+                };
+                let set = match field.set {
+                    Some(set) => format!(",\"set\":{}", set),
+                    None => String::new(),
+                };
+                format!(
+                    "{{\"name\":{},\"offset\":{},\"width\":{},\"value\":{},\"bits\":{}{}{}}}",
+                    json::string(field.name),
+                    field.offset,
+                    field.width,
+                    field.value,
+                    json::string(&bit_string(field)),
+                    consistent,
+                    set
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"offset\":{},\"length\":{},\"bytes\":{},\"type\":{},\"index\":{},\"format\":{},\
+             \"subformat\":{},\"fields\":[{}]}}",
+            self.offset,
+            self.bytes.len(),
+            json::string(&bytes.join(" ")),
+            json::string(self.kind()),
+            optional(self.field("index")),
+            optional(self.field("format")),
+            optional(self.field("subformat")),
+            fields.join(",")
+        )
+    }
+}
+
+/// The packet header line followed by one line per field
+impl std::fmt::Display for PacketDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        write!(
+            f,
+            "{:#06x}: {} ({} bytes: {})",
+            self.offset,
+            self.kind(),
+            self.bytes.len(),
+            bytes.join(" ")
+        )?;
+
+        for field in &self.fields {
+            let value = match field.name {
+                "branch_map" | "sign_extend" => format!("0b{}", bit_string(field)),
+                "address" => self.address_string(field),
+                "tvalepc" => format!("{:#010x}", field.value),
+                _ => field.value.to_string(),
+            };
+            let consistent = match field.consistent {
+                Some(true) => " consistent",
+                Some(false) => " INCONSISTENT",
+                None => "",
+            };
+            let set = match field.set {
+                Some(true) => " set",
+                Some(false) => " not set",
+                None => "",
+            };
+
+            write!(
+                f,
+                "\n    {:>4}..{:<4} {:<12} {}{}{}",
+                field.offset,
+                field.offset + field.width,
+                field.name,
+                value,
+                consistent,
+                set
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The bits of the field, most significant first
+fn bit_string(field: &Field) -> String {
+    (0..field.width)
+        .rev()
+        .map(|bit| {
+            if (field.value >> bit) & 1 != 0 {
+                '1'
+            } else {
+                '0'
+            }
+        })
+        .collect()
+}
+
+/// Split the trace data into packets and keep the bit fields of every packet
+pub fn dump(data: &[u8], config: &DecoderConfig) -> Result<Vec<PacketDump>, Error> {
+    let mut parser = PacketParser::with_config(config.clone())?;
+    parser.push(data);
+    parser.finish();
+
+    let mut res = Vec::new();
+    while let Some(dump) = parser.next_packet_dump()? {
+        res.push(dump);
+    }
+
+    Ok(res)
+}
+
+#[test]
+fn test_dump() {
+    let (_, trace) = crate::decoder::test_trace();
+
+    let dumps = dump(&trace, &DecoderConfig::default()).unwrap();
+    assert_eq!(dumps.len(), 4);
+
+    let branch_map = &dumps[1];
+    assert_eq!(branch_map.offset, 8);
+    assert_eq!(branch_map.kind(), "branch map with address");
+    let fields: Vec<(&str, usize, usize, u32)> = branch_map
+        .fields
+        .iter()
+        .map(|field| (field.name, field.offset, field.width, field.value))
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            ("length", 0, 5, 9),
+            ("reserved", 5, 3, 0),
+            ("index", 8, 16, 1),
+            ("format", 24, 2, 1),
+            ("branches", 26, 5, 3),
+            ("branch_map", 31, 3, 0b100),
+            ("address", 34, 31, 0x100),
+            ("notify", 65, 1, 0),
+            ("updiscon", 66, 1, 0),
+            ("sign_extend", 67, 5, 0),
+        ]
+    );
+    assert_eq!(branch_map.fields[9].consistent, Some(true));
+
+    let text = branch_map.to_string();
+    assert!(text.starts_with("0x0008: branch map with address (9 bytes: 09 01 00 "));
+    assert!(text.contains("\n      31..34   branch_map   0b100\n"));
+    assert!(text.ends_with("\n      67..72   sign_extend  0b00000 consistent"));
+
+    let json = dumps[3].to_json();
+    assert!(json.starts_with(
+        "{\"offset\":25,\"length\":4,\"bytes\":\"04 03 00 0f\",\"type\":\"support\",\"index\":3,\
+         \"format\":3,\"subformat\":3,\"fields\":[{\"name\":\"length\",\"offset\":0,\"width\":5,\
+         \"value\":4,\"bits\":\"00100\"},"
+    ));
+    assert!(json.ends_with(
+        "{\"name\":\"sign_extend\",\"offset\":31,\"width\":1,\"value\":0,\"bits\":\"0\",\
+         \"consistent\":true}]}"
+    ));
+}
+
+#[test]
+fn test_dump_inconsistent_sign_extension() {
+    // an address packet with updiscon set but sign extension bits clear
+    let packet = crate::decoder::test_packet(&[
+        (0, 16),
+        (0b10, 2),
+        (0x100 >> 1, 31),
+        (0, 1),
+        (1, 1),
+        (0, 5),
+    ]);
+    let mut parser = PacketParser::new();
+    parser.push(&packet);
+    parser.finish();
+
+    let dump = parser.next_packet_dump().unwrap().unwrap();
+    assert_eq!(dump.fields.last().unwrap().consistent, Some(false));
+    assert!(dump.to_string().ends_with("0b00000 INCONSISTENT"));
+}
+
+#[test]
+fn test_dump_configured_addresses() {
+    use crate::trace_decoder::{Address, Sync};
+
+    let config = DecoderConfig {
+        address_mode: AddressMode::Differential,
+        iaddress_lsb: 2,
+        ..Default::default()
+    };
+    let packets = [
+        Packet::Sync(
+            0,
+            Sync {
+                branch: true,
+                privilege: false,
+                address: 0x4200_0100,
+            },
+        ),
+        Packet::Address(
+            1,
+            Address {
+                address: 0x4200_00f0,
+                notify: false,
+                updiscon: false,
+            },
+        ),
+    ];
+    let trace = crate::trace_encoder::encode(&packets, &config).unwrap();
+    let dumps = dump(&trace, &config).unwrap();
+
+    // sync packets carry the full address even in differential mode
+    assert!(dumps[0]
+        .to_string()
+        .contains(" address      0x42000100 (0x10800040 << 2)\n"));
+    assert!(dumps[1]
+        .to_string()
+        .contains(" address      delta -0x10 (0x3ffffffc << 2) -> 0x420000f0\n"));
+}
+
+#[test]
+fn test_dump_flags() {
+    // the address MSB is set so a notify bit of 1 means the flag isn't set
+    let packet = crate::decoder::test_packet(&[
+        (0, 16),
+        (0b10, 2),
+        (0x4000_0080, 31),
+        (1, 1),
+        (0, 1),
+        (0, 5),
+    ]);
+    let mut parser = PacketParser::new();
+    parser.push(&packet);
+    parser.finish();
+
+    let dump = parser.next_packet_dump().unwrap().unwrap();
+    assert!(matches!(
+        dump.packet,
+        Some(Packet::Address(
+            _,
+            crate::trace_decoder::Address {
+                notify: false,
+                updiscon: true,
+                ..
+            }
+        ))
+    ));
+
+    let text = dump.to_string();
+    assert!(text.contains("\n      57..58   notify       1 not set\n"));
+    assert!(text.contains("\n      58..59   updiscon     0 set\n"));
+
+    let json = dump.to_json();
+    assert!(json.contains(
+        "{\"name\":\"notify\",\"offset\":57,\"width\":1,\"value\":1,\"bits\":\"1\",\"set\":false}"
+    ));
+    assert!(json.contains(
+        "{\"name\":\"updiscon\",\"offset\":58,\"width\":1,\"value\":0,\"bits\":\"0\",\"set\":true}"
+    ));
+}
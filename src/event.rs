@@ -0,0 +1,80 @@
+use crate::trap::Trap;
+
+/// What happened during execution, reconstructed from the trace packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// Bytes in front of the first valid packet were skipped, e.g. after the trace buffer wrapped
+    Discarded { bytes: usize },
+    /// Packets got lost, reconstruction continues at the next sync packet
+    Gap { expected: u32, index: u32 },
+    /// A support packet reported the encoder got enabled
+    TraceStart,
+    /// A support packet reported the encoder got disabled
+    TraceStop,
+    /// A support packet reported the encoder lost packets, reconstruction continues at the next
+    /// sync packet
+    TraceLost,
+    /// A sync packet reported the full PC
+    Resync { pc: u32 },
+    /// An instruction was executed, 16 bit instructions are zero-extended
+    Instruction { pc: u32, insn: u32 },
+    /// The branch instruction at `from` was taken
+    BranchTaken { from: u32, to: u32 },
+    /// The branch instruction at `pc` was not taken
+    BranchNotTaken { pc: u32 },
+    /// An uninferable jump whose target was reported by the trace
+    IndirectJump { from: u32, to: u32 },
+    /// A function return (`ret`) or trap return (`mret`)
+    Return { from: u32, to: u32 },
+    /// A trigger requested a notification at the instruction at `pc`
+    Notify { pc: u32 },
+    /// An exception or interrupt
+    Trap(Trap),
+}
+
+impl std::fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceEvent::Discarded { bytes } => {
+                write!(f, "discarded {} bytes before the first packet", bytes)
+            }
+            TraceEvent::Gap { expected, index } => write!(
+                f,
+                "lost {} packets, expected index {} got {}",
+                index.wrapping_sub(*expected) & 0xffff,
+                expected,
+                index
+            ),
+            TraceEvent::TraceStart => write!(f, "trace start"),
+            TraceEvent::TraceStop => write!(f, "trace stop"),
+            TraceEvent::TraceLost => write!(f, "trace lost"),
+            TraceEvent::Resync { pc } => write!(f, "resync at {:#010x}", pc),
+            TraceEvent::Instruction { pc, insn } => {
+                if insn & 0b11 == 0b11 {
+                    write!(f, "{:#010x}: {:08x}", pc, insn)
+                } else {
+                    write!(f, "{:#010x}: {:04x}", pc, insn)
+                }
+            }
+            TraceEvent::BranchTaken { from, to } => {
+                write!(f, "branch taken {:#010x} -> {:#010x}", from, to)
+            }
+            TraceEvent::BranchNotTaken { pc } => write!(f, "branch not taken {:#010x}", pc),
+            TraceEvent::IndirectJump { from, to } => {
+                write!(f, "indirect jump {:#010x} -> {:#010x}", from, to)
+            }
+            TraceEvent::Return { from, to } => write!(f, "return {:#010x} -> {:#010x}", from, to),
+            TraceEvent::Notify { pc } => write!(f, "notify at {:#010x}", pc),
+            TraceEvent::Trap(trap) => write!(f, "trap: {}", trap),
+        }
+    }
+}
+
+/// Instruction events for the given execution path
+#[cfg(test)]
+pub(crate) fn instructions(execution_path: &[u32]) -> Vec<TraceEvent> {
+    execution_path
+        .iter()
+        .map(|pc| TraceEvent::Instruction { pc: *pc, insn: 0 })
+        .collect()
+}
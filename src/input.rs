@@ -0,0 +1,287 @@
+use crate::Error;
+
+/// The line the example firmware prints right before the hex encoded trace data
+pub const TRACE_MARKER: &str = "Copy the trace data to a file";
+
+/// How captured trace data is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Guess the format from the content
+    Auto,
+    /// Raw bytes as dumped from the trace buffer
+    Binary,
+    /// Hex digits, whitespace and `0x` prefixes are ignored
+    Hex,
+    /// Output of the example firmware, the hex data follows the marker line
+    Log,
+}
+
+/// Information from the `TraceResult` debug line printed by the example firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceResult {
+    pub valid_start_index: usize,
+    pub valid_length: usize,
+}
+
+/// Turn captured trace data into the raw trace bytes
+pub fn parse_input(input: &[u8], format: InputFormat) -> Result<Vec<u8>, Error> {
+    let format = match format {
+        InputFormat::Auto => detect_format(input),
+        format => format,
+    };
+    log::debug!("input format {:?}", format);
+
+    match format {
+        InputFormat::Auto => unreachable!(),
+        InputFormat::Binary => Ok(input.to_vec()),
+        InputFormat::Hex => parse_hex(text(input)?, 1),
+        InputFormat::Log => parse_log(text(input)?),
+    }
+}
+
+fn detect_format(input: &[u8]) -> InputFormat {
+    let text = match std::str::from_utf8(input) {
+        Ok(text) if !text.contains('\0') => text,
+        _ => return InputFormat::Binary,
+    };
+
+    if text.contains(TRACE_MARKER) || text.contains("TraceResult") {
+        InputFormat::Log
+    } else {
+        InputFormat::Hex
+    }
+}
+
+fn text(input: &[u8]) -> Result<&str, Error> {
+    std::str::from_utf8(input).map_err(|_| Error::InvalidInput {
+        line: 1,
+        column: 1,
+        message: "the trace data is not text".to_string(),
+    })
+}
+
+/// Parse hex digits, `first_line` is the line number used in error messages
+fn parse_hex(text: &str, first_line: usize) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    let mut high_nibble: Option<(u8, usize, usize)> = None;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = first_line + line_index;
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((column, c)) = chars.next() {
+            if c.is_whitespace() || c == ',' {
+                continue;
+            }
+
+            if c == '0' && matches!(chars.peek(), Some((_, 'x' | 'X'))) {
+                chars.next();
+                continue;
+            }
+
+            let nibble = c.to_digit(16).ok_or_else(|| Error::InvalidInput {
+                line: line_number,
+                column: column + 1,
+                message: format!("'{}' is not a hex digit", c),
+            })? as u8;
+
+            high_nibble = match high_nibble {
+                Some((high, _, _)) => {
+                    data.push(high << 4 | nibble);
+                    None
+                }
+                None => Some((nibble, line_number, column + 1)),
+            };
+        }
+    }
+
+    if let Some((_, line, column)) = high_nibble {
+        return Err(Error::InvalidInput {
+            line,
+            column,
+            message: "odd number of hex digits".to_string(),
+        });
+    }
+
+    Ok(data)
+}
+
+fn parse_log(text: &str) -> Result<Vec<u8>, Error> {
+    let mut scanner = LogScanner::new();
+    for line in text.lines() {
+        if let Some(data) = scanner.line(line)? {
+            return Ok(data);
+        }
+    }
+
+    Err(Error::InvalidInput {
+        line: scanner.line_number().max(1),
+        column: 1,
+        message: format!("no hex data after \"{}\"", TRACE_MARKER),
+    })
+}
+
+/// Finds the trace dumps in the output of the example firmware, line by line
+pub struct LogScanner {
+    line_number: usize,
+    after_marker: bool,
+    trace_result: Option<TraceResult>,
+}
+
+impl LogScanner {
+    pub fn new() -> Self {
+        Self {
+            line_number: 0,
+            after_marker: false,
+            trace_result: None,
+        }
+    }
+
+    /// Number of lines seen so far
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Feed the next line, returns the trace data once a complete dump was seen
+    pub fn line(&mut self, line: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.line_number += 1;
+
+        if let Some(result) = parse_trace_result(line) {
+            self.trace_result = Some(result);
+        }
+
+        if line.contains(TRACE_MARKER) {
+            self.after_marker = true;
+            return Ok(None);
+        }
+
+        if !self.after_marker || line.trim().is_empty() {
+            return Ok(None);
+        }
+
+        self.after_marker = false;
+        let data = parse_hex(line, self.line_number)?;
+
+        if let Some(trace_result) = self.trace_result.take() {
+            if trace_result.valid_length != data.len() {
+                log::warn!(
+                    "{:?} but got {} bytes of trace data",
+                    trace_result,
+                    data.len()
+                );
+            }
+        }
+
+        Ok(Some(data))
+    }
+}
+
+impl Default for LogScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parse a line like `TraceResult { valid_start_index: 0, valid_length: 1234 }`
+pub fn parse_trace_result(line: &str) -> Option<TraceResult> {
+    let fields = line.split("TraceResult").nth(1)?;
+
+    let field = |name: &str| -> Option<usize> {
+        let value = fields.split(name).nth(1)?.trim_start_matches([':', ' ']);
+        let end = value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len());
+        value[..end].parse().ok()
+    };
+
+    Some(TraceResult {
+        valid_start_index: field("valid_start_index")?,
+        valid_length: field("valid_length")?,
+    })
+}
+
+#[test]
+fn test_parse_hex() {
+    assert_eq!(
+        parse_input(b"0a1B ff\r\n0x12 0x34\n", InputFormat::Hex).unwrap(),
+        [0x0a, 0x1b, 0xff, 0x12, 0x34]
+    );
+
+    let err = parse_input(b"0a1b\n0g", InputFormat::Hex).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::InvalidInput {
+            line: 2,
+            column: 2,
+            ..
+        }
+    ));
+
+    let err = parse_input(b"0a1b\n0", InputFormat::Hex).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::InvalidInput {
+            line: 2,
+            column: 1,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_parse_log() {
+    let log = "Hello\n\
+        TraceResult { valid_start_index: 16, valid_length: 3 }\n\
+        Copy the trace data to a file and use the CLI to decode\n\
+        \n\
+        0a1b2c\n";
+
+    assert_eq!(
+        parse_input(log.as_bytes(), InputFormat::Log).unwrap(),
+        [0x0a, 0x1b, 0x2c]
+    );
+    assert_eq!(
+        parse_trace_result(log.lines().nth(1).unwrap()),
+        Some(TraceResult {
+            valid_start_index: 16,
+            valid_length: 3
+        })
+    );
+
+    let err = parse_input(b"Hello\n", InputFormat::Log).unwrap_err();
+    assert!(matches!(err, Error::InvalidInput { .. }));
+}
+
+#[test]
+fn test_detect_format() {
+    assert_eq!(detect_format(b"0a1b 2c\n"), InputFormat::Hex);
+    assert_eq!(
+        detect_format(b"Copy the trace data to a file and use the CLI to decode\n0a1b"),
+        InputFormat::Log
+    );
+    assert_eq!(
+        detect_format(&[0x08, 0x00, 0x00, 0xc0]),
+        InputFormat::Binary
+    );
+}
+
+#[test]
+fn test_log_scanner_multiple_dumps() {
+    let mut scanner = LogScanner::new();
+    let mut dumps = Vec::new();
+
+    for line in [
+        "Hello",
+        "Copy the trace data to a file and use the CLI to decode",
+        "0a1b",
+        "Hello again",
+        "Copy the trace data to a file and use the CLI to decode",
+        "2c3d\r",
+    ] {
+        if let Some(data) = scanner.line(line).unwrap() {
+            dumps.push(data);
+        }
+    }
+
+    assert_eq!(dumps, [vec![0x0a, 0x1b], vec![0x2c, 0x3d]]);
+}
@@ -1,8 +1,5 @@
-#[derive(Debug, Copy, Clone, PartialEq, Default)]
-pub struct RiscvRegisters {
-    pub pc: u32,
-    pub registers: [u32; 32],
-}
+// bit patterns are grouped by instruction fields rather than by nibbles
+#![allow(clippy::unusual_byte_groupings)]
 
 #[derive(Debug)]
 pub struct NextAddress {
@@ -13,7 +10,7 @@ pub struct NextAddress {
 pub fn next_address(insn: &[u8], pc: u32) -> NextAddress {
     let candidates = estimate_next_inferable_pc(insn, pc);
 
-    let next_instruction = if candidates.len() > 0 {
+    let next_instruction = if !candidates.is_empty() {
         Some(candidates[0])
     } else {
         None
@@ -268,9 +265,81 @@ pub fn is_uninferable_branch(insn: &[u8]) -> bool {
     }
 }
 
+pub fn is_call(insn: &[u8]) -> bool {
+    let insn_len = if insn[0] & 0b11 == 0b11 { 4 } else { 2 };
+
+    match insn_len {
+        4 => {
+            let inst = u32::from_le_bytes(insn.try_into().unwrap());
+            let rd = (inst & 0b11111_00000_00) >> 7;
+
+            if (inst & 0b111_1111) == 0b110_1111 && rd == 1 {
+                // JAL ra, ..
+                true
+            } else if (inst & 0b111_00000_11111_11) == 0b000_00000_11001_11 && rd == 1 {
+                // JALR ra, ..
+                true
+            } else {
+                false
+            }
+        }
+        2 => {
+            let inst = u16::from_le_bytes(insn[0..2].try_into().unwrap());
+
+            if (inst & 0b111_00000000000_11) == 0b001_00000000000_01 {
+                // C.JAL
+                true
+            } else if (inst & 0b1111_0000_0111_1111) == 0b1001_0000_0000_0010
+                && inst != 0b1001000000000010
+            {
+                // C.JALR (but not C.EBREAK)
+                true
+            } else {
+                false
+            }
+        }
+        _ => panic!("Unexpected insn_len"),
+    }
+}
+
+pub fn is_return(insn: &[u8]) -> bool {
+    let insn_len = if insn[0] & 0b11 == 0b11 { 4 } else { 2 };
+
+    match insn_len {
+        4 => {
+            let inst = u32::from_le_bytes(insn.try_into().unwrap());
+
+            // JALR x0, 0(ra)
+            (inst & 0b11111_111_11111_1111111) == 0b00001_000_00000_1100111
+        }
+        2 => {
+            let inst = u16::from_le_bytes(insn[0..2].try_into().unwrap());
+
+            // C.JR ra aka C.RET
+            inst == 0b1000_00001_00000_10
+        }
+        _ => panic!("Unexpected insn_len"),
+    }
+}
+
+pub fn is_trap_return(insn: &[u8]) -> bool {
+    let insn_len = if insn[0] & 0b11 == 0b11 { 4 } else { 2 };
+
+    match insn_len {
+        4 => {
+            let inst = u32::from_le_bytes(insn.try_into().unwrap());
+
+            // MRET
+            inst == 0b00110000001000000000000001110011
+        }
+        2 => false,
+        _ => panic!("Unexpected insn_len"),
+    }
+}
+
 fn sext(value: u32, sign_bit: usize) -> i32 {
     if value & (1 << sign_bit) != 0 {
-        ((0b1 << (sign_bit - 1)) - (value & setbits(sign_bit - 1)) as i32) * -1
+        -((0b1 << (sign_bit - 1)) - (value & setbits(sign_bit - 1)) as i32)
     } else {
         value as i32
     }
@@ -390,8 +459,8 @@ fn test_branching_compressed_beqz3() {
     let pc = 0x42002dda;
     let isn = [0xd5, 0xcc];
 
-    assert_eq!(true, is_inferable_branch(&isn));
-    assert_eq!(false, is_uninferable_branch(&isn));
+    assert!(is_inferable_branch(&isn));
+    assert!(!is_uninferable_branch(&isn));
 
     let res = estimate_next_inferable_pc(&isn, pc);
     assert_eq!(res.len(), 2);
@@ -404,11 +473,35 @@ fn test_uncompressed_j() {
     let pc = 0x40000058;
     let isn = [0x6f, 0x20, 0x32, 0x48];
 
-    assert_eq!(false, is_inferable_branch(&isn));
-    assert_eq!(false, is_uninferable_branch(&isn));
-    assert_eq!(true, is_inferable_jump(&isn));
+    assert!(!is_inferable_branch(&isn));
+    assert!(!is_uninferable_branch(&isn));
+    assert!(is_inferable_jump(&isn));
 
     let res = estimate_next_inferable_pc(&isn, pc);
     assert_eq!(res.len(), 1);
     assert_eq!(res[0], 0x40022cda);
 }
+
+#[test]
+fn test_calls_and_returns() {
+    // jal ra, 0x16c
+    assert!(is_call(&[0xef, 0x00, 0xc0, 0x16]));
+    // j 0x22c80 (jal x0)
+    assert!(!is_call(&[0x6f, 0x20, 0x32, 0x48]));
+    // jalr ra, 0(a5)
+    assert!(is_call(&[0xe7, 0x80, 0x07, 0x00]));
+    // c.jalr a5
+    assert!(is_call(&[0x82, 0x97]));
+    // c.ebreak
+    assert!(!is_call(&[0x02, 0x90]));
+    // c.jr a5
+    assert!(!is_call(&[0x82, 0x87]));
+    assert!(!is_return(&[0x82, 0x87]));
+
+    // ret
+    assert!(is_return(&[0x82, 0x80]));
+    // jalr x0, 0(ra)
+    assert!(is_return(&[0x67, 0x80, 0x00, 0x00]));
+    // jalr x0, 0(a5)
+    assert!(!is_return(&[0x67, 0x80, 0x07, 0x00]));
+}
@@ -0,0 +1,28 @@
+/// Quote and escape a string for JSON output
+pub(crate) fn string(value: &str) -> String {
+    let mut res = String::with_capacity(value.len() + 2);
+    res.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[test]
+fn test_string() {
+    assert_eq!(string("foo"), "\"foo\"");
+    assert_eq!(string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    assert_eq!(
+        string("<T as core::fmt::Debug>"),
+        "\"<T as core::fmt::Debug>\""
+    );
+}
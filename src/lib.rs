@@ -1,169 +1,345 @@
 use std::path::PathBuf;
+pub(crate) mod branch_map;
+pub(crate) mod call_tree;
+pub(crate) mod cfg;
+pub(crate) mod code_map;
+pub(crate) mod config;
+pub(crate) mod coverage;
+pub(crate) mod decoder;
+pub(crate) mod disasm;
+pub(crate) mod dump;
+pub(crate) mod event;
+pub(crate) mod input;
 pub(crate) mod inst_decoder;
+pub(crate) mod json;
+pub(crate) mod profile;
+pub(crate) mod simulator;
+pub(crate) mod symbols;
 pub(crate) mod trace_decoder;
+pub(crate) mod trace_encoder;
+pub(crate) mod trap;
 use object::{File, Object, ObjectSection};
 
-use crate::trace_decoder::*;
+pub use crate::call_tree::{Call, CallTree};
+pub use crate::cfg::{BasicBlock, ControlFlowGraph, Edge, EdgeKind, FunctionGraph};
+pub use crate::config::{AddressMode, DecoderConfig};
+pub use crate::coverage::Coverage;
+pub use crate::decoder::{Decoded, Decoder};
+pub use crate::disasm::{disassemble, Disassembly};
+pub use crate::dump::{dump as dump_packets, Field, PacketDump};
+pub use crate::event::TraceEvent;
+pub use crate::input::{
+    parse_input, parse_trace_result, InputFormat, LogScanner, TraceResult, TRACE_MARKER,
+};
+pub use crate::profile::{BasicBlockProfile, FunctionProfile, Profile};
+pub use crate::symbols::{Location, Symbolizer};
+pub use crate::trace_decoder::{
+    parse as parse_packets, Address, AddressBranchMap, BranchCount, Exception, JumpTargetIndex,
+    NoAddressBranchMap, Packet, PacketParser, Support, Sync,
+};
+pub use crate::trace_encoder::{encode as encode_packets, PacketEncoder};
+pub use crate::trap::Trap;
 
-#[derive(Debug, Clone, Copy)]
+/// Internals used by the benchmarks, not part of the API
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::trace_decoder::Reader;
+}
+
+#[derive(Debug)]
 pub enum Error {
+    /// The trace data doesn't make sense
     Corrupted,
+    /// An ELF file couldn't be read
+    ElfRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// An ELF file couldn't be parsed
+    ElfParse {
+        path: PathBuf,
+        source: object::Error,
+    },
+    /// A packet is shorter than its fields, `needed` is the length required to read the next
+    /// field
+    TruncatedPacket { offset: usize, needed: usize },
+    /// A packet has a value which doesn't fit into its field
+    UnencodablePacket { index: u32, reason: &'static str },
+    /// The execution path continues at an address the instruction can't reach
+    UnreachableInstruction { from: u32, to: u32 },
+    /// The trace data doesn't contain a sync packet to start decoding from
+    NoSyncPacket,
+    /// The trace data doesn't contain any packets
+    EmptyTrace,
+    /// None of the ELF files contains the traced address
+    InstructionNotFound { pc: u32 },
+    /// The traced address doesn't contain a valid instruction
+    IllegalInstruction { pc: u32, bytes: Vec<u8> },
+    /// An inferable branch was reached but the trace data didn't contain the branch outcome
+    BranchMapUnderflow { packet_index: u32 },
+    /// The trace data file couldn't be read
+    TraceRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The encoder configuration describes packets which can't exist
+    InvalidConfig { message: String },
+    /// The decoder can't reconstruct the execution from this kind of packet
+    UnsupportedPacket { packet_index: u32 },
+    /// The captured trace data isn't in the expected format
+    InvalidInput {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Corrupted => write!(f, "the trace data is corrupted"),
+            Error::ElfRead { path, source } => {
+                write!(f, "failed to read ELF file {}: {}", path.display(), source)
+            }
+            Error::ElfParse { path, source } => {
+                write!(f, "failed to parse ELF file {}: {}", path.display(), source)
+            }
+            Error::TruncatedPacket { offset, needed } => write!(
+                f,
+                "the packet at byte offset {} is truncated, at least {} bytes are needed",
+                offset, needed
+            ),
+            Error::UnencodablePacket { index, reason } => {
+                write!(
+                    f,
+                    "packet with index {} can't be encoded: {}",
+                    index, reason
+                )
+            }
+            Error::UnreachableInstruction { from, to } => write!(
+                f,
+                "the instruction at {:#010x} can't continue at {:#010x}",
+                from, to
+            ),
+            Error::NoSyncPacket => write!(f, "the trace data contains no sync packet"),
+            Error::EmptyTrace => write!(f, "the trace data contains no packets"),
+            Error::InstructionNotFound { pc } => {
+                write!(f, "no instruction found at {:#010x} in the ELF files", pc)
+            }
+            Error::IllegalInstruction { pc, bytes } => {
+                write!(f, "illegal instruction {:02x?} at {:#010x}", bytes, pc)
+            }
+            Error::BranchMapUnderflow { packet_index } => write!(
+                f,
+                "ran out of branch map bits after packet with index {}",
+                packet_index
+            ),
+            Error::TraceRead { path, source } => {
+                write!(
+                    f,
+                    "failed to read trace data {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            Error::InvalidConfig { message } => {
+                write!(f, "invalid encoder configuration: {}", message)
+            }
+            Error::UnsupportedPacket { packet_index } => write!(
+                f,
+                "decoding packets like the one with index {} isn't supported",
+                packet_index
+            ),
+            Error::InvalidInput {
+                line,
+                column,
+                message,
+            } => write!(f, "invalid trace data at {}:{}: {}", line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ElfRead { source, .. } => Some(source),
+            Error::ElfParse { source, .. } => Some(source),
+            Error::TraceRead { source, .. } => Some(source),
+            _ => None,
+        }
+    }
 }
 
 /// Parse the given trace data by using the given ELF files
-pub fn parse_trace(data: Vec<u8>, elf_files: &Vec<PathBuf>) -> Result<Vec<u32>, Error> {
-    let mut execution_path = Vec::new();
+pub fn parse_trace(
+    data: Vec<u8>,
+    elf_files: &[PathBuf],
+    config: &DecoderConfig,
+) -> Result<Vec<u32>, Error> {
+    let elfs = load_elfs(elf_files)?;
+    let obj_files = parse_elfs(elf_files, &elfs)?;
+
+    reconstruct(&data, &obj_files, config)
+}
+
+/// Parse the given trace data by using the given ELF files and reconstruct what happened
+pub fn decode_events(
+    data: Vec<u8>,
+    elf_files: &[PathBuf],
+    config: &DecoderConfig,
+) -> Result<Vec<TraceEvent>, Error> {
+    let elfs = load_elfs(elf_files)?;
+    let obj_files = parse_elfs(elf_files, &elfs)?;
+
+    decode(&data, &obj_files, config)
+}
+
+/// Parse the given trace data by using the given ELF files and reconstruct the calls
+pub fn parse_call_tree(
+    data: Vec<u8>,
+    elf_files: &[PathBuf],
+    config: &DecoderConfig,
+) -> Result<CallTree, Error> {
+    let elfs = load_elfs(elf_files)?;
+    let obj_files = parse_elfs(elf_files, &elfs)?;
 
-    let parsed = parse(&data)?;
+    let events = decode(&data, &obj_files, config)?;
 
-    // load elfs
+    Ok(CallTree::new(&events, &obj_files))
+}
+
+/// Parse the given trace data by using the given ELF files and profile where the instructions
+/// were spent
+pub fn parse_profile(
+    data: Vec<u8>,
+    elf_files: &[PathBuf],
+    config: &DecoderConfig,
+) -> Result<Profile, Error> {
+    let elfs = load_elfs(elf_files)?;
+    let obj_files = parse_elfs(elf_files, &elfs)?;
+
+    let events = decode(&data, &obj_files, config)?;
+    let symbolizer = Symbolizer::from_obj_files(&obj_files);
+
+    Ok(Profile::new(&events, &obj_files, &symbolizer))
+}
+
+/// Parse the given traces by using the given ELF files and collect the covered source lines and
+/// branches of all of them
+pub fn parse_coverage(
+    traces: &[Vec<u8>],
+    elf_files: &[PathBuf],
+    config: &DecoderConfig,
+) -> Result<Coverage, Error> {
+    let elfs = load_elfs(elf_files)?;
+    let obj_files = parse_elfs(elf_files, &elfs)?;
+
+    let mut coverage = Coverage::new(&obj_files);
+    for data in traces {
+        coverage.add_events(&decode(data, &obj_files, config)?);
+    }
+
+    Ok(coverage)
+}
+
+/// Parse the given trace data by using the given ELF files and annotate the control flow graphs
+/// of the functions with the executed blocks and edges
+pub fn parse_cfg(
+    data: Vec<u8>,
+    elf_files: &[PathBuf],
+    config: &DecoderConfig,
+) -> Result<ControlFlowGraph, Error> {
+    let elfs = load_elfs(elf_files)?;
+    let obj_files = parse_elfs(elf_files, &elfs)?;
+
+    let execution_path = reconstruct(&data, &obj_files, config)?;
+
+    let mut cfg = ControlFlowGraph::new(&obj_files);
+    cfg.add_execution_path(&execution_path);
+    Ok(cfg)
+}
+
+/// Create the trace data the trace encoder emits for the given execution path, e.g. to test the
+/// decoder against a known result
+pub fn simulate_trace(
+    execution_path: &[u32],
+    elf_files: &[PathBuf],
+    config: &DecoderConfig,
+) -> Result<Vec<u8>, Error> {
+    let elfs = load_elfs(elf_files)?;
+    let obj_files = parse_elfs(elf_files, &elfs)?;
+
+    encode_packets(
+        &crate::simulator::simulate(&obj_files, execution_path, config)?,
+        config,
+    )
+}
+
+pub(crate) fn load_elfs(elf_files: &[PathBuf]) -> Result<Vec<Vec<u8>>, Error> {
     let mut elfs = Vec::new();
-    let mut obj_files = Vec::new();
     for elf in elf_files {
-        let bin_data = std::fs::read(elf).unwrap();
+        let bin_data = std::fs::read(elf).map_err(|source| Error::ElfRead {
+            path: elf.clone(),
+            source,
+        })?;
         elfs.push(bin_data);
     }
-    for i in 0..elfs.len() {
-        let obj_file = object::File::parse(&*elfs[i]).unwrap();
+    Ok(elfs)
+}
+
+pub(crate) fn parse_elfs<'a>(
+    elf_files: &[PathBuf],
+    elfs: &'a [Vec<u8>],
+) -> Result<Vec<File<'a>>, Error> {
+    let mut obj_files = Vec::new();
+    for (path, elf) in elf_files.iter().zip(elfs) {
+        let obj_file = object::File::parse(&**elf).map_err(|source| Error::ElfParse {
+            path: path.clone(),
+            source,
+        })?;
         obj_files.push(obj_file);
     }
+    Ok(obj_files)
+}
 
-    log::debug!("Parsed {:#x?}", &parsed);
-
-    let (first_sync, _) = parsed
-        .iter()
-        .enumerate()
-        .find(|(_, packet)| matches!(packet, Packet::Sync(_, _)))
-        .unwrap();
-    log::debug!("first sync packet at index {first_sync}");
-
-    let last_packet = if let Packet::Support(_, _) = *parsed.last().unwrap() {
-        parsed.len() - 2
-    } else {
-        log::debug!("Last packet is not a support packet. Data corrupted?");
-        parsed.len() - 1
-    };
+fn reconstruct(
+    data: &[u8],
+    obj_files: &[File<'_>],
+    config: &DecoderConfig,
+) -> Result<Vec<u32>, Error> {
+    Ok(decode(data, obj_files, config)?
+        .into_iter()
+        .filter_map(|event| match event {
+            TraceEvent::Instruction { pc, .. } => Some(pc),
+            _ => None,
+        })
+        .collect())
+}
 
-    let end_pc = if let Packet::Address(_, addr) = parsed[last_packet] {
-        addr.address
-    } else {
-        log::debug!("No data packet before support ending packet.");
-        return Err(Error::Corrupted);
-    };
+fn decode(
+    data: &[u8],
+    obj_files: &[File<'_>],
+    config: &DecoderConfig,
+) -> Result<Vec<TraceEvent>, Error> {
+    let mut events = Vec::new();
+    let mut decoder = Decoder::with_config(obj_files, config.clone())?;
 
-    let mut branch_map: Vec<bool> = Vec::new();
-    let mut current = first_sync;
-    let mut pc = 0;
-    let mut uninferable = false;
-    let mut last_taken_branch_map = None;
-
-    'outer: loop {
-        match parsed[current] {
-            Packet::Sync(_, sync) => {
-                // should a sync be considered an address for uninferable branches?
-                pc = sync.address;
-
-                let insn = get_instruction(&obj_files, pc);
-                // if an inferable branch -> push if it should be taken or not
-                if crate::inst_decoder::is_inferable_branch(&insn) {
-                    log::debug!(
-                        "sync is an inferable branch, branch taken = {}",
-                        !sync.branch
-                    );
-                    branch_map.insert(0, !sync.branch);
-                }
-                current += 1;
-            }
-            Packet::Address(_, address) => {
-                pc = address.address;
-                current += 1;
-            }
-            Packet::AddressBranchMap(_, map) => {
-                if uninferable {
-                    pc = map.address;
-                    current += 1;
-                } else {
-                    if last_taken_branch_map == Some(current) {
-                        current += 1;
-                    }
-                    last_taken_branch_map = Some(current);
-                    let count = if map.branches != 0 { map.branches } else { 32 };
-                    for i in 0..count {
-                        branch_map.push(((map.branch_map >> i) & 0b1) == 0);
-                    }
-                }
-            }
-            Packet::NoAddressBranchMap(_, map) => {
-                let count = if map.branches != 0 { map.branches } else { 32 };
-                for i in 0..count {
-                    branch_map.push(((map.branch_map >> i) & 0b1) == 0);
-                }
-                current += 1;
-            }
-            _ => {
-                current += 1;
-                continue;
-            }
+    for decoded in decoder.push(data)? {
+        if let Decoded::Event(event) = decoded {
+            events.push(event);
         }
-        uninferable = false;
-
-        loop {
-            if execution_path.is_empty() || execution_path[execution_path.len() - 1] != pc {
-                execution_path.push(pc);
-            }
-
-            if pc == end_pc {
-                break 'outer;
-            }
-
-            log::debug!("PC={:x}", pc);
-            let insn = get_instruction(&obj_files, pc);
-            log::debug!("  Instruction {:x?}", &insn);
-
-            log::debug!(
-                "   {:x?} infer={} uninfer={} jmp-infer={}",
-                crate::inst_decoder::next_address(&insn, pc),
-                crate::inst_decoder::is_inferable_branch(&insn),
-                crate::inst_decoder::is_uninferable_branch(&insn),
-                crate::inst_decoder::is_inferable_jump(&insn),
-            );
-            if !crate::inst_decoder::is_inferable_branch(&insn)
-                && !crate::inst_decoder::is_uninferable_branch(&insn)
-                && !crate::inst_decoder::is_inferable_jump(&insn)
-            {
-                pc = crate::inst_decoder::next_address(&insn, pc)
-                    .next_instruction
-                    .unwrap();
-            } else if crate::inst_decoder::is_inferable_branch(&insn) {
-                if branch_map.is_empty() {
-                    log::debug!("empty branch map");
-                    continue 'outer;
-                }
+    }
 
-                log::debug!("take from branch map");
-                let taken = *branch_map.first().unwrap();
-                branch_map.remove(0);
-                let next = crate::inst_decoder::next_address(&insn, pc);
-                pc = if taken {
-                    next.branched.unwrap()
-                } else {
-                    next.next_instruction.unwrap()
-                };
-            } else if crate::inst_decoder::is_inferable_jump(&insn) {
-                let next = crate::inst_decoder::next_address(&insn, pc);
-                pc = next.next_instruction.unwrap();
-            } else if crate::inst_decoder::is_uninferable_branch(&insn) {
-                log::info!("uninferable branch");
-                uninferable = true;
-                continue 'outer;
-            }
+    for decoded in decoder.finish()? {
+        if let Decoded::Event(event) = decoded {
+            events.push(event);
         }
     }
 
-    Ok(execution_path)
+    Ok(events)
 }
 
-pub fn get_instruction(obj_files: &Vec<File<'_>>, address: u32) -> Vec<u8> {
+pub fn get_instruction(obj_files: &[File<'_>], address: u32) -> Vec<u8> {
     let mut res = Vec::new();
     for obj_file in obj_files {
         for section in obj_file.sections() {
@@ -179,10 +355,137 @@ pub fn get_instruction(obj_files: &Vec<File<'_>>, address: u32) -> Vec<u8> {
             }
         }
 
-        if res.len() > 0 {
+        if !res.is_empty() {
             break;
         }
     }
 
     res
 }
+
+#[cfg(test)]
+pub(crate) fn test_elf(text: &[u8], symbols: &[(&str, u64, u64)]) -> Vec<u8> {
+    test_object(text, symbols).write().unwrap()
+}
+
+/// A test ELF with a DWARF line table mapping `(address, line)` rows to `/src/{file}`
+#[cfg(test)]
+pub(crate) fn test_elf_with_lines(
+    text: &[u8],
+    symbols: &[(&str, u64, u64)],
+    file: &str,
+    rows: &[(u64, u64)],
+) -> Vec<u8> {
+    use gimli::write::{
+        Address, AttributeValue, DwarfUnit, EndianVec, LineProgram, LineString, Sections,
+    };
+    use gimli::{Encoding, Format, LineEncoding, LittleEndian};
+
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 4,
+        address_size: 4,
+    };
+    let mut dwarf = DwarfUnit::new(encoding);
+
+    let mut program = LineProgram::new(
+        encoding,
+        LineEncoding::default(),
+        LineString::String(b"/src".to_vec()),
+        LineString::String(file.as_bytes().to_vec()),
+        None,
+    );
+    let directory = program.default_directory();
+    let file_id = program.add_file(
+        LineString::String(file.as_bytes().to_vec()),
+        directory,
+        None,
+    );
+    program.begin_sequence(Some(Address::Constant(rows[0].0)));
+    for (address, line) in rows {
+        program.row().address_offset = address - rows[0].0;
+        program.row().file = file_id;
+        program.row().line = *line;
+        program.generate_row();
+    }
+    program.end_sequence(text.len() as u64 - rows[0].0);
+    dwarf.unit.line_program = program;
+
+    let root = dwarf.unit.root();
+    let root = dwarf.unit.get_mut(root);
+    root.set(
+        gimli::DW_AT_name,
+        AttributeValue::String(file.as_bytes().to_vec()),
+    );
+    root.set(
+        gimli::DW_AT_comp_dir,
+        AttributeValue::String(b"/src".to_vec()),
+    );
+    root.set(
+        gimli::DW_AT_low_pc,
+        AttributeValue::Address(Address::Constant(rows[0].0)),
+    );
+    root.set(
+        gimli::DW_AT_high_pc,
+        AttributeValue::Udata(text.len() as u64 - rows[0].0),
+    );
+
+    let mut sections = Sections::new(EndianVec::new(LittleEndian));
+    dwarf.write(&mut sections).unwrap();
+
+    let mut obj = test_object(text, symbols);
+    sections
+        .for_each(|id, data| {
+            if !data.slice().is_empty() {
+                let section = obj.add_section(
+                    Vec::new(),
+                    id.name().as_bytes().to_vec(),
+                    object::SectionKind::Debug,
+                );
+                obj.append_section_data(section, data.slice(), 1);
+            }
+            Ok::<(), gimli::write::Error>(())
+        })
+        .unwrap();
+
+    obj.write().unwrap()
+}
+
+#[cfg(test)]
+fn test_object(text: &[u8], symbols: &[(&str, u64, u64)]) -> object::write::Object<'static> {
+    use object::write::{Object, Symbol, SymbolSection};
+    use object::{
+        Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+    };
+
+    let mut obj = Object::new(BinaryFormat::Elf, Architecture::Riscv32, Endianness::Little);
+    let section = obj.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+    obj.append_section_data(section, text, 2);
+
+    for (name, address, size) in symbols {
+        obj.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: *address,
+            size: *size,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Section(section),
+            flags: SymbolFlags::None,
+        });
+    }
+
+    obj
+}
+
+#[test]
+fn test_missing_elf() {
+    let res = parse_trace(
+        Vec::new(),
+        &[PathBuf::from("does-not-exist.elf")],
+        &DecoderConfig::default(),
+    );
+    assert!(
+        matches!(res, Err(Error::ElfRead { path, .. }) if path.as_os_str() == "does-not-exist.elf")
+    );
+}
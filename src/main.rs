@@ -1,13 +1,213 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::io::BufRead;
 use std::path::PathBuf;
-use tracedecode::parse_trace;
+use std::time::Duration;
+use tracedecode::{
+    decode_events, disassemble, dump_packets, parse_call_tree, parse_cfg, parse_coverage,
+    parse_input, parse_profile, parse_trace, Call, DecoderConfig, Error, InputFormat, LogScanner,
+    Profile, Symbolizer, TraceEvent,
+};
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    trace_file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Trace data files, `-` reads the output of the example firmware from stdin
+    #[arg(required_unless_present = "serial")]
+    trace_files: Vec<PathBuf>,
+
+    /// Read the output of the example firmware from a serial port
+    #[arg(short, long, conflicts_with = "trace_files")]
+    serial: Option<String>,
+
+    /// Baud rate of the serial port
+    #[arg(long, default_value_t = 115_200)]
+    baud: u32,
+
+    /// Keep decoding every trace dump from stdin or the serial port instead of only the first one
+    #[arg(long)]
+    follow: bool,
 
     #[arg(short, long)]
     elf: Vec<PathBuf>,
+
+    /// How the trace data file is encoded
+    #[arg(short, long, value_enum, default_value_t = Format::Auto)]
+    format: Format,
+
+    /// How to print the decoded execution path
+    #[arg(short, long, value_enum, default_value_t = Output::Addresses)]
+    output: Output,
+
+    /// Number of basic blocks listed in the profile
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+
+    /// Print the executed instructions disassembled, like `objdump -d`
+    #[arg(long, conflicts_with = "output")]
+    disasm: bool,
+
+    #[command(flatten)]
+    encoder: EncoderArgs,
+}
+
+/// Parameters of the trace encoder, the defaults match the ESP32-C6 and ESP32-H2
+#[derive(Args)]
+#[command(next_help_heading = "Encoder")]
+struct EncoderArgs {
+    /// How branch map and address packets report addresses
+    #[arg(long, value_enum, default_value_t = AddressMode::Full)]
+    address_mode: AddressMode,
+
+    /// Number of low address bits which are always 0 and not reported
+    #[arg(long, default_value_t = 1)]
+    iaddress_lsb: u32,
+
+    /// Number of address bits
+    #[arg(long, default_value_t = 32)]
+    iaddress_width: u32,
+
+    /// Possible widths of the branch map field in ascending order
+    #[arg(long, value_delimiter = ',', default_values_t = [1, 3, 7, 15, 31])]
+    branch_map_widths: Vec<u32>,
+
+    /// Parse format 0 packets (branch count and jump target index)
+    #[arg(long)]
+    format0: bool,
+
+    /// Number of bits of the jump target index in format 0 packets
+    #[arg(long, default_value_t = 0)]
+    jump_target_cache_size: u32,
+}
+
+impl EncoderArgs {
+    fn config(&self) -> DecoderConfig {
+        DecoderConfig {
+            address_mode: self.address_mode.into(),
+            iaddress_lsb: self.iaddress_lsb,
+            iaddress_width: self.iaddress_width,
+            branch_map_widths: self.branch_map_widths.clone(),
+            format0_extensions: self.format0,
+            jump_target_cache_size: self.jump_target_cache_size,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the bit fields of every packet without decoding the execution path
+    Dump {
+        #[arg(required = true)]
+        trace_files: Vec<PathBuf>,
+
+        /// How the trace data file is encoded
+        #[arg(short, long, value_enum, default_value_t = Format::Auto)]
+        format: Format,
+
+        /// Print a JSON array with one object per packet
+        #[arg(long)]
+        json: bool,
+
+        #[command(flatten)]
+        encoder: EncoderArgs,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AddressMode {
+    /// Full addresses
+    Full,
+    /// Addresses relative to the previously reported one
+    Differential,
+}
+
+impl From<AddressMode> for tracedecode::AddressMode {
+    fn from(mode: AddressMode) -> Self {
+        match mode {
+            AddressMode::Full => tracedecode::AddressMode::Full,
+            AddressMode::Differential => tracedecode::AddressMode::Differential,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Detect the format from the file content
+    Auto,
+    /// Raw trace buffer dump
+    Binary,
+    /// Hex digits, whitespace and 0x prefixes are ignored
+    Hex,
+    /// Output of the example firmware
+    Log,
+}
+
+impl From<Format> for InputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Auto => InputFormat::Auto,
+            Format::Binary => InputFormat::Binary,
+            Format::Hex => InputFormat::Hex,
+            Format::Log => InputFormat::Log,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// Plain list of PCs
+    Addresses,
+    /// PCs resolved to function+offset and source line
+    Symbols,
+    /// Indented tree of calls with instruction counts
+    CallTree,
+    /// Executed instructions, branch outcomes, jumps and traps
+    Events,
+    /// Instructions per function and the hottest basic blocks
+    Profile,
+    /// Line and branch coverage as lcov tracefile
+    Lcov,
+    /// Calls as Chrome Trace Event Format JSON, timestamps are instruction indices
+    ChromeTrace,
+    /// Folded stacks weighted by executed instructions, for flamegraph tools
+    Folded,
+    /// Basic block graph of every executed function as Graphviz DOT, with taken counts
+    Cfg,
+}
+
+fn dump(
+    trace_files: &[PathBuf],
+    format: Format,
+    json: bool,
+    config: &DecoderConfig,
+) -> Result<(), Error> {
+    let mut objects = Vec::new();
+    for trace_file in trace_files {
+        let input = std::fs::read(trace_file).map_err(|source| Error::TraceRead {
+            path: trace_file.clone(),
+            source,
+        })?;
+        let packets = dump_packets(&parse_input(&input, format.into())?, config)?;
+
+        if json {
+            objects.extend(packets.iter().map(|packet| packet.to_json()));
+            continue;
+        }
+        if trace_files.len() > 1 {
+            println!("{}:", trace_file.display());
+        }
+        for packet in &packets {
+            println!("{}", packet);
+        }
+    }
+
+    if json {
+        println!("[\n{}\n]", objects.join(",\n"));
+    }
+
+    Ok(())
 }
 
 fn main() {
@@ -15,18 +215,282 @@ fn main() {
 
     let cli = Cli::parse();
 
-    let hex_trace = std::fs::read_to_string(&cli.trace_file).unwrap();
-    let mut data: Vec<u8> = Vec::new();
+    if let Err(err) = run(&cli) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Error> {
+    if let Some(Command::Dump {
+        trace_files,
+        format,
+        json,
+        encoder,
+    }) = &cli.command
+    {
+        return dump(trace_files, *format, *json, &encoder.config());
+    }
+
+    if let Some(port) = &cli.serial {
+        let serial = serialport::new(port, cli.baud)
+            .timeout(Duration::from_secs(1))
+            .open()
+            .map_err(|source| Error::TraceRead {
+                path: PathBuf::from(port),
+                source: source.into(),
+            })?;
+        return decode_dumps(cli, PathBuf::from(port), std::io::BufReader::new(serial));
+    }
+
+    if let [trace_file] = &cli.trace_files[..] {
+        if trace_file.as_os_str() == "-" {
+            return decode_dumps(cli, trace_file.clone(), std::io::stdin().lock());
+        }
+    }
+
+    let mut traces = Vec::new();
+    for trace_file in &cli.trace_files {
+        let input = std::fs::read(trace_file).map_err(|source| Error::TraceRead {
+            path: trace_file.clone(),
+            source,
+        })?;
+        traces.push(parse_input(&input, cli.format.into())?);
+    }
+
+    // the coverage of all traces goes into a single report
+    if cli.output == Output::Lcov && !cli.disasm {
+        print!(
+            "{}",
+            parse_coverage(&traces, &cli.elf, &cli.encoder.config())?.to_lcov()
+        );
+        return Ok(());
+    }
 
-    let hex_chars: Vec<char> = hex_trace.chars().into_iter().collect();
-    for i in (0..hex_trace.len()).step_by(2) {
-        let b = u8::from_str_radix(&format!("{}{}", hex_chars[i], hex_chars[i + 1]), 16).unwrap();
-        data.push(b);
+    for data in traces {
+        decode(cli, data)?;
     }
 
+    Ok(())
+}
+
+/// Decode the trace dumps found in the output of the example firmware
+fn decode_dumps(cli: &Cli, path: PathBuf, mut reader: impl BufRead) -> Result<(), Error> {
+    let mut scanner = LogScanner::new();
+    let mut line = Vec::new();
+
+    loop {
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            // the serial port times out when the device is quiet
+            Err(err) if err.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(source) => return Err(Error::TraceRead { path, source }),
+        }
+
+        let res = scanner
+            .line(&String::from_utf8_lossy(&line))
+            .and_then(|data| match data {
+                Some(data) => decode(cli, data).map(|_| true),
+                None => Ok(false),
+            });
+        line.clear();
+
+        match res {
+            Ok(true) if !cli.follow => return Ok(()),
+            Ok(_) => (),
+            Err(err) if cli.follow => eprintln!("Error: {}", err),
+            Err(err) => return Err(err),
+        }
+    }
+
+    if cli.follow {
+        Ok(())
+    } else {
+        Err(Error::InvalidInput {
+            line: scanner.line_number().max(1),
+            column: 1,
+            message: "no trace dump found".to_string(),
+        })
+    }
+}
+
+fn decode(cli: &Cli, data: Vec<u8>) -> Result<(), Error> {
     let elf_files = &cli.elf;
+    let config = &cli.encoder.config();
 
-    let execution_path = parse_trace(data, elf_files);
+    if cli.disasm {
+        let symbolizer = Symbolizer::new(elf_files)?;
+        print_disassembly(&decode_events(data, elf_files, config)?, &symbolizer);
+        return Ok(());
+    }
+
+    match cli.output {
+        Output::Addresses => {
+            let execution_path = parse_trace(data, elf_files, config)?;
+            println!("{:#x?}", &execution_path);
+        }
+        Output::Symbols => {
+            let symbolizer = Symbolizer::new(elf_files)?;
+            for pc in parse_trace(data, elf_files, config)? {
+                println!("{}", symbolizer.symbolize(pc));
+            }
+        }
+        Output::CallTree => {
+            let symbolizer = Symbolizer::new(elf_files)?;
+            let call_tree = parse_call_tree(data, elf_files, config)?;
+            for root in &call_tree.roots {
+                print_call(root, 0, &symbolizer);
+            }
+        }
+        Output::Events => {
+            for event in decode_events(data, elf_files, config)? {
+                println!("{}", event);
+            }
+        }
+        Output::Profile => {
+            let symbolizer = Symbolizer::new(elf_files)?;
+            let profile = parse_profile(data, elf_files, config)?;
+            print_profile(&profile, cli.top, &symbolizer);
+        }
+        Output::Lcov => {
+            print!("{}", parse_coverage(&[data], elf_files, config)?.to_lcov());
+        }
+        Output::ChromeTrace => {
+            let symbolizer = Symbolizer::new(elf_files)?;
+            let call_tree = parse_call_tree(data, elf_files, config)?;
+            print!("{}", call_tree.to_chrome_trace(&symbolizer));
+        }
+        Output::Folded => {
+            let symbolizer = Symbolizer::new(elf_files)?;
+            let call_tree = parse_call_tree(data, elf_files, config)?;
+            print!("{}", call_tree.to_folded_stacks(&symbolizer));
+        }
+        Output::Cfg => {
+            print!("{}", parse_cfg(data, elf_files, config)?.to_dot());
+        }
+    }
 
-    println!("{:#x?}", &execution_path);
+    Ok(())
+}
+
+fn symbol_name(symbolizer: &Symbolizer, address: u32) -> Option<String> {
+    let location = symbolizer.symbolize(address);
+    match location.function {
+        Some(function) if location.offset == 0 => Some(function),
+        Some(function) => Some(format!("{}+{:#x}", function, location.offset)),
+        None => None,
+    }
+}
+
+fn print_call(call: &Call, depth: usize, symbolizer: &Symbolizer) {
+    let name =
+        symbol_name(symbolizer, call.address).unwrap_or_else(|| format!("{:#010x}", call.address));
+
+    println!(
+        "{:indent$}{} ({} instructions, {} self)",
+        "",
+        name,
+        call.total_instructions(),
+        call.instructions,
+        indent = depth * 2
+    );
+
+    for child in &call.children {
+        print_call(child, depth + 1, symbolizer);
+    }
+}
+
+fn print_profile(profile: &Profile, top: usize, symbolizer: &Symbolizer) {
+    let percent = |instructions: usize| {
+        instructions as f64 * 100.0 / profile.total_instructions.max(1) as f64
+    };
+
+    println!("{} instructions", profile.total_instructions);
+    println!();
+    println!(
+        "{:>10} {:>7} {:>10} {:>7} {:>7}  function",
+        "self", "self%", "inclusive", "incl%", "calls"
+    );
+    for function in &profile.functions {
+        let name = function
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("{:#010x}", function.address));
+        println!(
+            "{:>10} {:>6.2}% {:>10} {:>6.2}% {:>7}  {}",
+            function.self_instructions,
+            percent(function.self_instructions),
+            function.inclusive_instructions,
+            percent(function.inclusive_instructions),
+            function.calls,
+            name
+        );
+    }
+
+    println!();
+    println!(
+        "{:>10} {:>7} {:>10}  basic block",
+        "insns", "insns%", "executions"
+    );
+    for block in profile.hottest_blocks(top) {
+        let name = symbol_name(symbolizer, block.address)
+            .map(|name| format!(" <{}>", name))
+            .unwrap_or_default();
+        println!(
+            "{:>10} {:>6.2}% {:>10}  {:#010x}..={:#010x}{}",
+            block.instructions,
+            percent(block.instructions),
+            block.executions,
+            block.address,
+            block.end,
+            name
+        );
+    }
+}
+
+fn print_disassembly(events: &[TraceEvent], symbolizer: &Symbolizer) {
+    let mut function = None;
+
+    for event in events {
+        let (pc, insn) = match *event {
+            TraceEvent::Instruction { pc, insn } => (pc, insn),
+            _ => continue,
+        };
+
+        // a label whenever execution enters another function, like the symbol headers of objdump
+        let location = symbolizer.symbolize(pc);
+        if function.as_ref() != Some(&location.function) {
+            match &location.function {
+                Some(name) if location.offset == 0 => println!("\n{:08x} <{}>:", pc, name),
+                Some(name) => println!("\n{:08x} <{}+{:#x}>:", pc, name, location.offset),
+                None => println!("\n{:08x}:", pc),
+            }
+            function = Some(location.function);
+        }
+
+        let bytes = if insn & 0b11 == 0b11 {
+            insn.to_le_bytes().to_vec()
+        } else {
+            (insn as u16).to_le_bytes().to_vec()
+        };
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let disassembly = disassemble(insn, pc);
+        let target = disassembly
+            .target
+            .and_then(|target| symbol_name(symbolizer, target))
+            .map(|name| format!(" <{}>", name))
+            .unwrap_or_default();
+
+        let line = format!(
+            "{:8x}: {:<12} {:<8} {}{}",
+            pc,
+            bytes.join(" "),
+            disassembly.mnemonic,
+            disassembly.operands,
+            target
+        );
+        println!("{}", line.trim_end());
+    }
 }
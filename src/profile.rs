@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use object::File;
+
+use crate::call_tree::{Call, CallTree};
+use crate::code_map::CodeMap;
+use crate::event::TraceEvent;
+use crate::inst_decoder::{is_inferable_branch, is_inferable_jump, is_uninferable_branch};
+use crate::symbols::Symbolizer;
+
+/// Instructions executed in a function, summed over all its invocations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    /// Start of the function, the first traced address if there's no symbol for it
+    pub address: u32,
+    pub name: Option<String>,
+    /// Instructions executed in the function itself
+    pub self_instructions: usize,
+    /// Instructions executed in the function and its callees, recursive calls are counted once
+    pub inclusive_instructions: usize,
+    /// Number of traced calls to the function
+    pub calls: usize,
+}
+
+/// A straight sequence of instructions which ends with a jump or branch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlockProfile {
+    pub address: u32,
+    /// The last instruction of the block
+    pub end: u32,
+    /// How often the block was entered
+    pub executions: usize,
+    /// Instructions executed in the block, summed over all executions
+    pub instructions: usize,
+}
+
+/// Where the traced instructions were spent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub total_instructions: usize,
+    /// Sorted by self instructions, hottest first
+    pub functions: Vec<FunctionProfile>,
+    /// Sorted by executed instructions, hottest first
+    pub basic_blocks: Vec<BasicBlockProfile>,
+}
+
+impl Profile {
+    /// Build the profile from the decoded events
+    ///
+    /// Instructions are attributed to the function a call entered, see [CallTree].
+    pub fn new(events: &[TraceEvent], obj_files: &[File<'_>], symbolizer: &Symbolizer) -> Self {
+        let call_tree = CallTree::new(events, obj_files);
+        let execution_path: Vec<u32> = events
+            .iter()
+            .filter_map(|event| match event {
+                TraceEvent::Instruction { pc, .. } => Some(*pc),
+                _ => None,
+            })
+            .collect();
+
+        let mut functions = HashMap::new();
+        for root in &call_tree.roots {
+            add_call(root, symbolizer, &mut functions, &mut Vec::new());
+        }
+
+        let mut functions: Vec<FunctionProfile> = functions
+            .into_values()
+            .filter(|function| function.inclusive_instructions != 0)
+            .collect();
+        functions.sort_by(|a, b| {
+            b.self_instructions
+                .cmp(&a.self_instructions)
+                .then(a.address.cmp(&b.address))
+        });
+
+        Self {
+            total_instructions: execution_path.len(),
+            functions,
+            basic_blocks: basic_blocks(&execution_path, obj_files),
+        }
+    }
+
+    /// The `count` hottest basic blocks
+    pub fn hottest_blocks(&self, count: usize) -> &[BasicBlockProfile] {
+        &self.basic_blocks[..count.min(self.basic_blocks.len())]
+    }
+}
+
+fn add_call(
+    call: &Call,
+    symbolizer: &Symbolizer,
+    functions: &mut HashMap<u32, FunctionProfile>,
+    active: &mut Vec<u32>,
+) {
+    let location = symbolizer.symbolize(call.address);
+    let address = call.address - location.offset;
+
+    let function = functions.entry(address).or_insert_with(|| FunctionProfile {
+        address,
+        name: location.function,
+        self_instructions: 0,
+        inclusive_instructions: 0,
+        calls: 0,
+    });
+
+    function.self_instructions += call.instructions;
+    if call.call_site.is_some() {
+        function.calls += 1;
+    }
+    // the outermost invocation already includes the recursive ones
+    if !active.contains(&address) {
+        function.inclusive_instructions += call.total_instructions();
+    }
+
+    active.push(address);
+    for child in &call.children {
+        add_call(child, symbolizer, functions, active);
+    }
+    active.pop();
+}
+
+fn basic_blocks(execution_path: &[u32], obj_files: &[File<'_>]) -> Vec<BasicBlockProfile> {
+    let code = CodeMap::new(obj_files);
+    let mut blocks: HashMap<u32, BasicBlockProfile> = HashMap::new();
+    let mut current: Option<BasicBlockProfile> = None;
+    let mut next_pc = None;
+
+    let mut finish = |block: Option<BasicBlockProfile>| {
+        if let Some(block) = block {
+            let entry = blocks.entry(block.address).or_insert(BasicBlockProfile {
+                address: block.address,
+                end: block.end,
+                executions: 0,
+                instructions: 0,
+            });
+            entry.end = entry.end.max(block.end);
+            entry.executions += 1;
+            entry.instructions += block.instructions;
+        }
+    };
+
+    for &pc in execution_path {
+        // execution didn't continue with the following instruction, e.g. because of a trap
+        if next_pc != Some(pc) {
+            finish(current.take());
+        }
+
+        let block = current.get_or_insert(BasicBlockProfile {
+            address: pc,
+            end: pc,
+            executions: 1,
+            instructions: 0,
+        });
+        block.end = pc;
+        block.instructions += 1;
+
+        let insn = code.instruction(pc);
+        let len = match insn.first() {
+            Some(byte) if byte & 0b11 == 0b11 => 4,
+            _ => 2,
+        };
+        let complete = insn.len() >= len;
+
+        next_pc = if !complete
+            || is_inferable_branch(insn)
+            || is_inferable_jump(insn)
+            || is_uninferable_branch(insn)
+        {
+            finish(current.take());
+            None
+        } else {
+            Some(pc + len as u32)
+        };
+    }
+    finish(current.take());
+
+    let mut blocks: Vec<BasicBlockProfile> = blocks.into_values().collect();
+    blocks.sort_by(|a, b| {
+        b.instructions
+            .cmp(&a.instructions)
+            .then(a.address.cmp(&b.address))
+    });
+    blocks
+}
+
+#[test]
+fn test_profile() {
+    let mut text = vec![0u8; 0x300];
+    // main: c.nop; c.bnez a0, main; jal ra, foo; c.nop
+    text[0x100..0x10a]
+        .copy_from_slice(&[0x01, 0x00, 0x7d, 0xfd, 0xef, 0x00, 0xc0, 0x0f, 0x01, 0x00]);
+    // foo: c.nop; ret
+    text[0x200..0x204].copy_from_slice(&[0x01, 0x00, 0x82, 0x80]);
+    let elf = crate::test_elf(&text, &[("main", 0x100, 0x10), ("foo", 0x200, 0x10)]);
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+    let symbolizer = Symbolizer::from_obj_files(&obj_files);
+
+    let events =
+        crate::event::instructions(&[0x100, 0x102, 0x100, 0x102, 0x104, 0x200, 0x202, 0x108]);
+    let profile = Profile::new(&events, &obj_files, &symbolizer);
+
+    assert_eq!(profile.total_instructions, 8);
+    assert_eq!(
+        profile.functions,
+        [
+            FunctionProfile {
+                address: 0x100,
+                name: Some("main".to_string()),
+                self_instructions: 6,
+                inclusive_instructions: 8,
+                calls: 0,
+            },
+            FunctionProfile {
+                address: 0x200,
+                name: Some("foo".to_string()),
+                self_instructions: 2,
+                inclusive_instructions: 2,
+                calls: 1,
+            },
+        ]
+    );
+
+    let blocks: Vec<(u32, u32, usize, usize)> = profile
+        .basic_blocks
+        .iter()
+        .map(|block| {
+            (
+                block.address,
+                block.end,
+                block.executions,
+                block.instructions,
+            )
+        })
+        .collect();
+    assert_eq!(
+        blocks,
+        [
+            (0x100, 0x102, 2, 4),
+            (0x200, 0x202, 1, 2),
+            (0x104, 0x104, 1, 1),
+            (0x108, 0x108, 1, 1),
+        ]
+    );
+    assert_eq!(profile.hottest_blocks(1).len(), 1);
+    assert_eq!(profile.hottest_blocks(10).len(), 4);
+}
+
+#[test]
+fn test_profile_recursion() {
+    let mut text = vec![0u8; 0x300];
+    // main: jal ra, foo; c.nop
+    text[0x100..0x106].copy_from_slice(&[0xef, 0x00, 0x00, 0x10, 0x01, 0x00]);
+    // foo: c.beqz a0, done; c.addi a0, -1; jal ra, foo; c.nop; done: ret
+    text[0x200..0x20c].copy_from_slice(&[
+        0x09, 0xc5, 0x7d, 0x15, 0xef, 0xf0, 0xdf, 0xff, 0x01, 0x00, 0x82, 0x80,
+    ]);
+    let elf = crate::test_elf(&text, &[("main", 0x100, 0x10), ("foo", 0x200, 0x10)]);
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+    let symbolizer = Symbolizer::from_obj_files(&obj_files);
+
+    // foo calls itself twice before returning through all invocations
+    let path = [
+        0x100, 0x200, 0x202, 0x204, 0x200, 0x202, 0x204, 0x200, 0x20a, 0x208, 0x20a, 0x208, 0x20a,
+        0x104,
+    ];
+    let config = crate::DecoderConfig::default();
+    let packets = crate::simulator::simulate(&obj_files, &path, &config).unwrap();
+    let trace = crate::encode_packets(&packets, &config).unwrap();
+    let events = crate::decode(&trace, &obj_files, &config).unwrap();
+
+    let profile = Profile::new(&events, &obj_files, &symbolizer);
+
+    assert_eq!(profile.total_instructions, path.len());
+    assert_eq!(
+        profile.functions,
+        [
+            FunctionProfile {
+                address: 0x200,
+                name: Some("foo".to_string()),
+                self_instructions: 12,
+                inclusive_instructions: 12,
+                calls: 3,
+            },
+            FunctionProfile {
+                address: 0x100,
+                name: Some("main".to_string()),
+                self_instructions: 2,
+                inclusive_instructions: 14,
+                calls: 0,
+            },
+        ]
+    );
+}
@@ -0,0 +1,318 @@
+use object::File;
+
+use crate::code_map::CodeMap;
+use crate::config::DecoderConfig;
+use crate::inst_decoder::{is_inferable_branch, is_uninferable_branch, next_address};
+use crate::trace_decoder::{Address, AddressBranchMap, NoAddressBranchMap, Packet, Support, Sync};
+use crate::Error;
+
+/// Software model of the trace encoder, creates the packets the hardware emits for a known
+/// execution path
+///
+/// Tracing starts with a sync packet at the first instruction. Branch outcomes are collected
+/// into maps of up to [DecoderConfig::max_branches] branches, an uninferable jump reports its
+/// target together with the pending branches. The last instruction is reported the same way,
+/// followed by a support packet stopping the trace. Traps aren't modelled, the path has to
+/// follow the control flow of the code.
+pub(crate) fn simulate(
+    obj_files: &[File<'_>],
+    execution_path: &[u32],
+    config: &DecoderConfig,
+) -> Result<Vec<Packet>, Error> {
+    let (first, last) = match execution_path {
+        [] => return Ok(Vec::new()),
+        [first, ..] => (*first, *execution_path.last().unwrap()),
+    };
+
+    let code = CodeMap::new(obj_files);
+    let mut encoder = Encoder {
+        max_branches: config.max_branches(),
+        ..Default::default()
+    };
+
+    // the outcome of a branch at the sync address is part of the sync packet
+    let insn = instruction(&code, first)?;
+    let branch_at_sync = is_inferable_branch(insn);
+    let not_taken = match execution_path.get(1) {
+        Some(next) if branch_at_sync => Some(*next) == next_address(insn, first).next_instruction,
+        _ => true,
+    };
+    encoder.emit(|index| {
+        Packet::Sync(
+            index,
+            Sync {
+                branch: not_taken,
+                privilege: false,
+                address: first,
+            },
+        )
+    });
+
+    for (i, window) in execution_path.windows(2).enumerate() {
+        let (pc, next) = (window[0], window[1]);
+        let insn = instruction(&code, pc)?;
+        let expected = next_address(insn, pc);
+
+        if is_inferable_branch(insn) {
+            let taken = if Some(next) == expected.next_instruction {
+                false
+            } else if Some(next) == expected.branched {
+                true
+            } else {
+                return Err(Error::UnreachableInstruction { from: pc, to: next });
+            };
+            if i != 0 || !branch_at_sync {
+                encoder.branch(taken);
+            }
+        } else if is_uninferable_branch(insn) {
+            encoder.address(next);
+        } else if Some(next) != expected.next_instruction {
+            return Err(Error::UnreachableInstruction { from: pc, to: next });
+        }
+    }
+
+    encoder.address(last);
+    encoder.emit(|index| {
+        Packet::Support(
+            index,
+            Support {
+                enable: false,
+                qual_status: 0,
+            },
+        )
+    });
+
+    Ok(encoder.packets)
+}
+
+fn instruction<'a>(code: &CodeMap<'a>, pc: u32) -> Result<&'a [u8], Error> {
+    let insn = code.instruction(pc);
+    if insn.is_empty() {
+        return Err(Error::InstructionNotFound { pc });
+    }
+    if (insn[0] & 0b11 == 0b11 && insn.len() < 4) || insn[0..2] == [0, 0] {
+        return Err(Error::IllegalInstruction {
+            pc,
+            bytes: insn.to_vec(),
+        });
+    }
+
+    Ok(insn)
+}
+
+#[derive(Default)]
+struct Encoder {
+    packets: Vec<Packet>,
+    /// Number of pending branches
+    branches: u32,
+    /// Outcomes of the pending branches, a set bit means not taken
+    branch_map: u32,
+    /// Number of branches in a full branch map
+    max_branches: u32,
+}
+
+impl Encoder {
+    fn emit(&mut self, packet: impl FnOnce(u32) -> Packet) {
+        let index = self.packets.len() as u32 & 0xffff;
+        self.packets.push(packet(index));
+    }
+
+    fn branch(&mut self, taken: bool) {
+        if !taken {
+            self.branch_map |= 1 << self.branches;
+        }
+        self.branches += 1;
+
+        if self.branches == self.max_branches {
+            let branch_map = self.branch_map;
+            self.emit(|index| {
+                Packet::NoAddressBranchMap(
+                    index,
+                    NoAddressBranchMap {
+                        branches: 0,
+                        branch_map,
+                    },
+                )
+            });
+            self.branches = 0;
+            self.branch_map = 0;
+        }
+    }
+
+    /// Report an address, the pending branches are flushed with it
+    fn address(&mut self, address: u32) {
+        let (branches, branch_map) = (self.branches, self.branch_map);
+        self.emit(|index| match branches {
+            0 => Packet::Address(
+                index,
+                Address {
+                    address,
+                    notify: false,
+                    updiscon: false,
+                },
+            ),
+            _ => Packet::AddressBranchMap(
+                index,
+                AddressBranchMap {
+                    address,
+                    branches: branches as u8,
+                    branch_map,
+                    notify: false,
+                    updiscon: false,
+                },
+            ),
+        });
+        self.branches = 0;
+        self.branch_map = 0;
+    }
+}
+
+/// A loop, a call of a function with another loop in a loop and a final instruction
+#[cfg(test)]
+pub(crate) fn test_elf() -> Vec<u8> {
+    let mut text = vec![0u8; 0x300];
+    // c.nop; c.bnez a0, 0x100; jal ra, 0x200; c.bnez a0, 0x104; c.nop
+    text[0x100..0x10c].copy_from_slice(&[
+        0x01, 0x00, 0x7d, 0xfd, 0xef, 0x00, 0xc0, 0x0f, 0x75, 0xfd, 0x01, 0x00,
+    ]);
+    // c.nop; c.bnez a0, 0x200; ret
+    text[0x200..0x206].copy_from_slice(&[0x01, 0x00, 0x7d, 0xfd, 0x82, 0x80]);
+    crate::test_elf(&text, &[])
+}
+
+/// The execution path of [test_elf] with the given iterations of the first loop and one call
+/// per entry of `calls` (at least one) with the given iterations of the loop in the function
+#[cfg(test)]
+pub(crate) fn test_path(first_loop: usize, calls: &[usize]) -> Vec<u32> {
+    let mut path = Vec::new();
+    for _ in 0..=first_loop {
+        path.extend([0x100, 0x102]);
+    }
+    for inner_loop in calls {
+        path.push(0x104);
+        for _ in 0..=*inner_loop {
+            path.extend([0x200, 0x202]);
+        }
+        path.extend([0x204, 0x108]);
+    }
+    path.push(0x10a);
+    path
+}
+
+#[test]
+fn test_simulate_test_trace() {
+    let (elf, trace) = crate::decoder::test_trace();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    let packets = simulate(
+        &obj_files,
+        crate::decoder::TEST_TRACE_PATH,
+        &crate::DecoderConfig::default(),
+    )
+    .unwrap();
+    let expected = crate::parse_packets(&trace, &crate::DecoderConfig::default()).unwrap();
+    assert_eq!(packets.len(), expected.len());
+    // the sync address isn't a branch
+    assert!(matches!(
+        packets[0],
+        Packet::Sync(
+            0,
+            Sync {
+                branch: true,
+                address: 0x100,
+                ..
+            }
+        )
+    ));
+    assert_eq!(packets[1..], expected[1..]);
+}
+
+#[test]
+fn test_simulate_full_branch_maps() {
+    let elf = test_elf();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    // 40 taken and one not taken branch, then a branch in the function before returning
+    let path = test_path(40, &[0]);
+    let packets = simulate(&obj_files, &path, &crate::DecoderConfig::default()).unwrap();
+    let kinds: Vec<(u32, u8, u32)> = packets
+        .iter()
+        .map(|packet| match *packet {
+            Packet::NoAddressBranchMap(index, map) => (index, 0, map.branch_map),
+            Packet::AddressBranchMap(index, map) => (index, map.branches, map.address),
+            Packet::Address(index, address) => (index, 0xff, address.address),
+            _ => (packet.index(), 0, 0),
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (0, 0, 0),
+            (1, 0, 0),
+            (2, 11, 0x108),
+            (3, 1, 0x10a),
+            (4, 0, 0),
+        ]
+    );
+    assert!(
+        matches!(packets[2], Packet::AddressBranchMap(_, map) if map.branch_map == 0b110_0000_0000)
+    );
+
+    let trace = crate::encode_packets(&packets, &crate::DecoderConfig::default()).unwrap();
+    assert_eq!(
+        crate::reconstruct(&trace, &obj_files, &crate::DecoderConfig::default()).unwrap(),
+        path
+    );
+}
+
+#[test]
+fn test_simulate_invalid_path() {
+    let elf = test_elf();
+    let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+    assert!(matches!(
+        simulate(&obj_files, &[0x100, 0x104], &DecoderConfig::default()),
+        Err(Error::UnreachableInstruction {
+            from: 0x100,
+            to: 0x104
+        })
+    ));
+    assert!(matches!(
+        simulate(&obj_files, &[0x102, 0x106], &DecoderConfig::default()),
+        Err(Error::UnreachableInstruction {
+            from: 0x102,
+            to: 0x106
+        })
+    ));
+    assert!(matches!(
+        simulate(&obj_files, &[0x400], &DecoderConfig::default()),
+        Err(Error::InstructionNotFound { pc: 0x400 })
+    ));
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_simulate_and_decode(
+        first_loop in 0..80usize,
+        calls in proptest::collection::vec(0..80usize, 1..4),
+        end in proptest::prelude::any::<proptest::sample::Index>(),
+        // the path contains compressed instructions
+        config in proptest::strategy::Strategy::prop_filter(
+            crate::trace_encoder::strategies::config(),
+            "addresses need bit 1",
+            |config| config.iaddress_lsb <= 1,
+        ),
+    ) {
+        let elf = test_elf();
+        let obj_files = vec![object::File::parse(&*elf).unwrap()];
+
+        // stop tracing anywhere, e.g. in the middle of a loop
+        let mut path = test_path(first_loop, &calls);
+        path.truncate(end.index(path.len()) + 1);
+
+        let packets = simulate(&obj_files, &path, &config).unwrap();
+        let trace = crate::encode_packets(&packets, &config).unwrap();
+        proptest::prop_assert_eq!(crate::reconstruct(&trace, &obj_files, &config).unwrap(), path);
+    }
+}
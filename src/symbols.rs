@@ -0,0 +1,219 @@
+use std::path::PathBuf;
+
+use addr2line::gimli::{EndianRcSlice, RunTimeEndian};
+use object::{Object, ObjectSymbol, SymbolKind};
+
+use crate::{load_elfs, parse_elfs, Error};
+
+/// Source information resolved for a single PC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub pc: u32,
+    pub function: Option<String>,
+    pub offset: u32,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#010x}", self.pc)?;
+
+        if let Some(function) = &self.function {
+            write!(f, " {}+{:#x}", function, self.offset)?;
+        }
+
+        if let Some(file) = &self.file {
+            write!(f, " ({}", file)?;
+            if let Some(line) = self.line {
+                write!(f, ":{}", line)?;
+            }
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    address: u32,
+    size: u32,
+    name: String,
+}
+
+/// Resolves PCs to function names (from the ELF symbol tables) and source lines (from DWARF)
+pub struct Symbolizer {
+    functions: Vec<Function>,
+    contexts: Vec<addr2line::Context<EndianRcSlice<RunTimeEndian>>>,
+}
+
+impl Symbolizer {
+    /// Create a symbolizer for the given ELF files
+    pub fn new(elf_files: &[PathBuf]) -> Result<Self, Error> {
+        let elfs = load_elfs(elf_files)?;
+        let obj_files = parse_elfs(elf_files, &elfs)?;
+
+        Ok(Self::from_obj_files(&obj_files))
+    }
+
+    pub(crate) fn from_obj_files(obj_files: &[object::File<'_>]) -> Self {
+        let mut symbolizer = Self {
+            functions: Vec::new(),
+            contexts: Vec::new(),
+        };
+
+        for obj_file in obj_files {
+            symbolizer.add_elf(obj_file);
+        }
+
+        symbolizer
+    }
+
+    fn add_elf(&mut self, obj_file: &object::File<'_>) {
+        for symbol in obj_file.symbols() {
+            if symbol.kind() != SymbolKind::Text || symbol.address() == 0 {
+                continue;
+            }
+
+            if let Ok(name) = symbol.name() {
+                self.functions.push(Function {
+                    address: symbol.address() as u32,
+                    size: symbol.size() as u32,
+                    name: format!("{:#}", rustc_demangle::demangle(name)),
+                });
+            }
+        }
+        self.functions.sort_by_key(|function| function.address);
+
+        // ELFs without debug info just don't contribute line information
+        if let Ok(context) = addr2line::Context::new(obj_file) {
+            self.contexts.push(context);
+        }
+    }
+
+    /// Resolve the given PC
+    pub fn symbolize(&self, pc: u32) -> Location {
+        let mut location = Location {
+            pc,
+            function: None,
+            offset: 0,
+            file: None,
+            line: None,
+        };
+
+        if let Some(function) = self.function(pc) {
+            location.function = Some(function.name.clone());
+            location.offset = pc - function.address;
+        }
+
+        for context in &self.contexts {
+            if let Ok(Some(loc)) = context.find_location(pc as u64) {
+                location.file = loc.file.map(|file| file.to_string());
+                location.line = loc.line;
+                break;
+            }
+        }
+
+        location
+    }
+
+    fn function(&self, pc: u32) -> Option<&Function> {
+        let idx = self
+            .functions
+            .partition_point(|function| function.address <= pc);
+        let candidates = &self.functions[..idx];
+
+        // prefer a symbol which covers the PC, fall back to the closest preceding one if it has
+        // no size information
+        candidates
+            .iter()
+            .rev()
+            .find(|function| pc - function.address < function.size)
+            .or_else(|| candidates.last().filter(|function| function.size == 0))
+    }
+}
+
+#[test]
+fn test_symbolize_function_offset() {
+    let mut symbolizer = Symbolizer::new(&[]).unwrap();
+    let elf = crate::test_elf(&[0u8; 0x100], &[("foo", 0x10, 0x20), ("bar", 0x30, 0x10)]);
+    symbolizer.add_elf(&object::File::parse(&*elf).unwrap());
+
+    let location = symbolizer.symbolize(0x34);
+    assert_eq!(location.function.as_deref(), Some("bar"));
+    assert_eq!(location.offset, 0x4);
+    assert_eq!(location.file, None);
+
+    let location = symbolizer.symbolize(0x10);
+    assert_eq!(location.function.as_deref(), Some("foo"));
+    assert_eq!(location.offset, 0x0);
+
+    let location = symbolizer.symbolize(0x40);
+    assert_eq!(location.function, None);
+}
+
+#[test]
+fn test_symbolize_demangles_rust_names() {
+    let mut symbolizer = Symbolizer::new(&[]).unwrap();
+    let elf = crate::test_elf(
+        &[0u8; 0x100],
+        &[("_ZN15example_esp32h24main17h0123456789abcdefE", 0x10, 0x8)],
+    );
+    symbolizer.add_elf(&object::File::parse(&*elf).unwrap());
+
+    let location = symbolizer.symbolize(0x12);
+    assert_eq!(location.function.as_deref(), Some("example_esp32h2::main"));
+    assert_eq!(location.to_string(), "0x00000012 example_esp32h2::main+0x2");
+}
+
+#[test]
+fn test_symbolize_without_size() {
+    let mut symbolizer = Symbolizer::new(&[]).unwrap();
+    let elf = crate::test_elf(
+        &[0u8; 0x100],
+        &[
+            ("_start", 0x10, 0),
+            ("foo", 0x20, 0x10),
+            ("bar", 0x50, 0),
+            ("top", 0xffff_fff0, 0x10),
+        ],
+    );
+    symbolizer.add_elf(&object::File::parse(&*elf).unwrap());
+
+    let location = symbolizer.symbolize(0x18);
+    assert_eq!(location.function.as_deref(), Some("_start"));
+    assert_eq!(location.offset, 0x8);
+
+    // past the end of `foo`, `_start` is too far away
+    assert_eq!(symbolizer.symbolize(0x40).function, None);
+
+    let location = symbolizer.symbolize(0x58);
+    assert_eq!(location.function.as_deref(), Some("bar"));
+    assert_eq!(location.offset, 0x8);
+
+    // ends at 4 GiB
+    let location = symbolizer.symbolize(0xffff_fffe);
+    assert_eq!(location.function.as_deref(), Some("top"));
+    assert_eq!(location.offset, 0xe);
+}
+
+#[test]
+fn test_symbolize_source_line() {
+    let mut symbolizer = Symbolizer::new(&[]).unwrap();
+    let elf = crate::test_elf_with_lines(
+        &[0u8; 0x200],
+        &[("main", 0x100, 0x20)],
+        "main.c",
+        &[(0x100, 10), (0x104, 12), (0x110, 15)],
+    );
+    symbolizer.add_elf(&object::File::parse(&*elf).unwrap());
+
+    let location = symbolizer.symbolize(0x106);
+    assert_eq!(location.function.as_deref(), Some("main"));
+    assert_eq!(location.file.as_deref(), Some("/src/main.c"));
+    assert_eq!(location.line, Some(12));
+    assert_eq!(location.to_string(), "0x00000106 main+0x6 (/src/main.c:12)");
+
+    assert_eq!(symbolizer.symbolize(0x110).line, Some(15));
+}
@@ -1,175 +1,413 @@
-pub fn parse(data: &[u8]) -> Result<Vec<Packet>, super::Error> {
+use crate::config::{AddressMode, DecoderConfig};
+use crate::dump::{Field, PacketDump};
+
+pub fn parse(data: &[u8], config: &DecoderConfig) -> Result<Vec<Packet>, super::Error> {
     let mut res = Vec::new();
-    let mut reader = Reader::new(&data);
+    let mut parser = PacketParser::with_config(config.clone())?;
+    parser.push(data);
+    parser.finish();
 
-    let mut previous_index = None;
-    while reader.has_data(8) {
-        let start_bit_count = reader.current_bit_count();
+    while let Some(packet) = parser.next_packet()? {
+        res.push(packet);
+    }
 
-        let len = reader.get_bits(5);
-        let until = start_bit_count + 8 * len as usize;
+    if parser.discarded() != 0 {
+        log::warn!(
+            "discarded {} bytes before the first packet",
+            parser.discarded()
+        );
+    }
 
-        if reader.total_bit_count() <= until {
-            break;
-        }
+    Ok(res)
+}
 
-        reader.get_bits(3);
+/// Splits trace data into packets, the data can be pushed in arbitrary chunks
+///
+/// The data doesn't need to start with a packet header, e.g. when the trace buffer wrapped
+/// around and the oldest packet got overwritten partially. Decoding starts at the first valid
+/// packet followed by a packet with the next index. Later gaps in the packet index (lost packets)
+/// don't stop parsing, detecting them is left to the consumer.
+pub struct PacketParser {
+    config: DecoderConfig,
+    /// The last reported address, differential addresses are relative to it
+    last_address: u32,
+    buffer: Vec<u8>,
+    /// Offset of the start of the buffer in the trace data
+    offset: usize,
+    position: usize,
+    previous_index: Option<u32>,
+    finished: bool,
+    discarded: usize,
+    /// Offset and length of the first packet header claiming more bytes than the trace
+    /// contains, reported if no valid packet is found
+    truncated: Option<(usize, usize)>,
+}
 
-        if len == 0 {
-            continue;
-        }
+impl PacketParser {
+    /// Create a parser for the default encoder configuration
+    pub fn new() -> Self {
+        Self::with_config(DecoderConfig::default()).unwrap()
+    }
+
+    /// Create a parser for traces of an encoder with the given configuration
+    pub fn with_config(config: DecoderConfig) -> Result<Self, super::Error> {
+        config.validate()?;
+
+        Ok(Self {
+            config,
+            last_address: 0,
+            buffer: Vec::new(),
+            offset: 0,
+            position: 0,
+            previous_index: None,
+            finished: false,
+            discarded: 0,
+            truncated: None,
+        })
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.offset += self.position;
+        self.buffer.drain(..self.position);
+        self.position = 0;
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Signal that no more data will be pushed
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
 
-        if !reader.has_data(len as usize * 8 - 8) {
-            break;
+    /// Number of bytes skipped before the first valid packet
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    /// Returns the next packet or `None` if more data is needed
+    pub fn next_packet(&mut self) -> Result<Option<Packet>, super::Error> {
+        while let Some(dump) = self.next(false)? {
+            if dump.packet.is_some() {
+                return Ok(dump.packet);
+            }
         }
 
-        let index = reader.get_bits(16);
-        if let Some(previous) = previous_index {
-            if previous != index.wrapping_sub(1) {
-                log::debug!("prev={} index={}", previous, index);
+        Ok(None)
+    }
+
+    /// Like [PacketParser::next_packet] but with the raw bit fields of the packet, packets of
+    /// unknown formats are returned too
+    pub fn next_packet_dump(&mut self) -> Result<Option<PacketDump>, super::Error> {
+        self.next(true)
+    }
+
+    fn next(&mut self, record: bool) -> Result<Option<PacketDump>, super::Error> {
+        while self.position < self.buffer.len() {
+            let len = (self.buffer[self.position] & 0b11111) as usize;
+
+            if len == 0 {
+                self.position += 1;
+                if self.previous_index.is_none() {
+                    self.discarded += 1;
+                }
+                continue;
+            }
+
+            if self.previous_index.is_none() {
+                if self.finished && self.buffer.len() - self.position < len {
+                    self.truncated
+                        .get_or_insert((self.offset + self.position, len));
+                }
+                match self.is_first_packet() {
+                    Some(true) => (),
+                    Some(false) => {
+                        self.position += 1;
+                        self.discarded += 1;
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+
+            if self.buffer.len() - self.position < len {
+                if self.finished {
+                    // the header claims more bytes than the trace contains
+                    return Err(super::Error::TruncatedPacket {
+                        offset: self.offset + self.position,
+                        needed: len,
+                    });
+                }
                 break;
             }
+
+            let offset = self.offset + self.position;
+            let bytes = &self.buffer[self.position..][..len];
+            let mut reader = Reader::new(bytes, offset);
+            if record {
+                reader.fields = Some(Vec::new());
+            }
+            reader.field("length", 5)?;
+            reader.field("reserved", 3)?;
+
+            let index = reader.field("index", 16)?;
+            if let Some(previous) = self.previous_index {
+                if index != (previous + 1) & 0xffff {
+                    log::warn!("packet index gap: prev={} index={}", previous, index);
+                }
+            }
+            self.previous_index = Some(index);
+
+            let packet = parse_packet(index, &mut reader, &self.config, &mut self.last_address)?;
+            self.position += len;
+
+            let (bytes, fields) = match reader.fields {
+                Some(fields) => (bytes.to_vec(), fields),
+                None => (Vec::new(), Vec::new()),
+            };
+            let dump = PacketDump::new(offset, bytes, packet, fields, &self.config);
+            return Ok(Some(dump));
+        }
+
+        match self.truncated {
+            Some((offset, needed)) if self.finished && self.previous_index.is_none() => {
+                Err(super::Error::TruncatedPacket { offset, needed })
+            }
+            _ => Ok(None),
         }
-        previous_index = Some(index);
-        let format = reader.get_bits(2);
+    }
+
+    /// Check if a valid packet starts at the current position, `None` if more data is needed
+    fn is_first_packet(&self) -> Option<bool> {
+        let data = &self.buffer[self.position..];
+        let len = (data[0] & 0b11111) as usize;
+
+        if data.len() < len {
+            return if self.finished { Some(false) } else { None };
+        }
+
+        let mut reader = Reader::new(&data[..len], self.offset + self.position);
+        let parsed = reader
+            .get_bits(8)
+            .and_then(|_| reader.get_bits(16))
+            .and_then(|index| {
+                // don't let a packet which turns out to be invalid change the parser's state
+                let mut last_address = self.last_address;
+                let packet = parse_packet(index, &mut reader, &self.config, &mut last_address)?;
+                Ok((index, packet))
+            });
+        let index = match parsed {
+            Ok((index, Some(_))) => index,
+            _ => return Some(false),
+        };
+
+        // the next packet has to continue the index
+        let next = match data[len..].iter().position(|byte| byte & 0b11111 != 0) {
+            Some(next) if data.len() >= len + next + 3 => len + next,
+            _ => return if self.finished { Some(true) } else { None },
+        };
+        let next_index = u16::from_le_bytes([data[next + 1], data[next + 2]]) as u32;
+
+        Some(next_index == (index + 1) & 0xffff)
+    }
+}
 
-        if format == 0b01 {
-            // format 1
+impl Default for PacketParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            let branches = reader.get_bits(5);
+fn parse_packet(
+    index: u32,
+    reader: &mut Reader,
+    config: &DecoderConfig,
+    last_address: &mut u32,
+) -> Result<Option<Packet>, super::Error> {
+    let mut packet = None;
+    let differential = config.address_mode == AddressMode::Differential;
+    let mut address = |reader: &mut Reader, differential: bool| {
+        let field = reader.field("address", config.address_bits())?;
+        *last_address = config.address(field, *last_address, differential);
+        Ok::<u32, super::Error>(*last_address)
+    };
+
+    let format = reader.field("format", 2)?;
+
+    if format == 0b00 && config.format0_extensions {
+        // format 0
+
+        let subformat = reader.field("subformat", 1)?;
+
+        if subformat == 0 {
+            let branch_count = reader.field("branch_count", 32)?;
+            let branch_fmt = reader.field("branch_fmt", 2)?;
+
+            let (address, notify, updiscon) = match branch_fmt {
+                0 => (None, false, false),
+                0b10 => {
+                    let address = address(reader, differential)?;
+                    let notify = reader.flag("notify")?;
+                    let updiscon = reader.flag("updiscon")?;
+                    (Some(address), notify, updiscon)
+                }
+                _ => return Ok(None),
+            };
+            reader.sign_extension()?;
 
+            packet = Some(Packet::BranchCount(
+                index,
+                BranchCount {
+                    branch_count,
+                    address,
+                    notify,
+                    updiscon,
+                },
+            ));
+        } else {
+            let cache_index =
+                reader.field("cache_index", config.jump_target_cache_size as usize)?;
+            let branches = reader.field("branches", 5)?;
             let bits = match branches {
                 0 => 0,
-                1 => 1,
-                2..=3 => 3,
-                4..=7 => 7,
-                8..=15 => 15,
-                16..=32 => 31,
-                _ => return Err(super::Error::Corrupted),
+                _ => config
+                    .branch_map_bits(branches)
+                    .ok_or(super::Error::Corrupted)?,
             };
+            let branch_map = reader.field("branch_map", bits)?;
+            let notify = reader.flag("notify")?;
+            let updiscon = reader.flag("updiscon")?;
+            reader.sign_extension()?;
 
-            let branch_map = reader.get_bits(if bits != 0 { bits } else { 31 });
-
-            if bits != 0 {
-                let address = reader.get_bits(31);
-                let bits = match branches {
-                    1 => 7,
-                    2..=3 => 5,
-                    4..=7 => 1,
-                    8..=15 => 1,
-                    16..=32 => 0, // ?? TRM says 31
-                    _ => return Err(super::Error::Corrupted),
-                };
-                let notify = reader.get_bits(1);
-                let updiscon = reader.get_bits(1);
-                let _sign_extend = reader.get_bits(bits);
-
-                res.push(Packet::AddressBranchMap(
-                    index,
-                    AddressBranchMap {
-                        address: address << 1,
-                        branches: branches as u8,
-                        branch_map,
-                        notify: notify != 0,
-                        updiscon: updiscon != 0,
-                    },
-                ))
-            } else {
-                let _sign_extend = reader.get_bits(2);
-
-                res.push(Packet::NoAddressBranchMap(
-                    index,
-                    NoAddressBranchMap {
-                        branches: branches as u8,
-                        branch_map,
-                    },
-                ))
-            }
-        } else if format == 0b10 {
-            // format 2
-
-            let address = reader.get_bits(31);
-            let notify = reader.get_bits(1);
-            let updiscon = reader.get_bits(1);
-            let _sign_extend = reader.get_bits(5);
+            packet = Some(Packet::JumpTargetIndex(
+                index,
+                JumpTargetIndex {
+                    cache_index,
+                    branches: branches as u8,
+                    branch_map,
+                    notify,
+                    updiscon,
+                },
+            ));
+        }
+    } else if format == 0b01 {
+        // format 1
+
+        let branches = reader.field("branches", 5)?;
+
+        if branches != 0 {
+            let bits = config
+                .branch_map_bits(branches)
+                .ok_or(super::Error::Corrupted)?;
+            let branch_map = reader.field("branch_map", bits)?;
+            let address = address(reader, differential)?;
+            let notify = reader.flag("notify")?;
+            let updiscon = reader.flag("updiscon")?;
+            reader.sign_extension()?;
+
+            packet = Some(Packet::AddressBranchMap(
+                index,
+                AddressBranchMap {
+                    address,
+                    branches: branches as u8,
+                    branch_map,
+                    notify,
+                    updiscon,
+                },
+            ));
+        } else {
+            // a full branch map
+            let branch_map = reader.field("branch_map", config.max_branches() as usize)?;
+            reader.sign_extension()?;
 
-            res.push(Packet::Address(
+            packet = Some(Packet::NoAddressBranchMap(
                 index,
-                Address {
-                    address: address << 1,
-                    notify: notify != 0,
-                    updiscon: updiscon != 0,
+                NoAddressBranchMap {
+                    branches: branches as u8,
+                    branch_map,
                 },
-            ))
-        } else if format == 0b11 {
-            // format 3
-
-            let subformat = reader.get_bits(2);
-
-            if subformat == 0 {
-                let branch = reader.get_bits(1);
-                let privilege = reader.get_bits(1);
-                let address = reader.get_bits(31);
-                let _sign_extend = reader.get_bits(3);
-
-                res.push(Packet::Sync(
-                    index,
-                    Sync {
-                        address: address << 1,
-                        branch: branch != 0,
-                        privilege: privilege != 0,
-                    },
-                ))
-            } else if subformat == 1 {
-                let branch = reader.get_bits(1);
-                let privilege = reader.get_bits(1);
-                let ecause = reader.get_bits(5);
-                let interrupt = reader.get_bits(1);
-                let address = reader.get_bits(31);
-                let tvalepc = reader.get_bits(32);
-                let _sign_extend = reader.get_bits(6);
-
-                res.push(Packet::Exception(
-                    index,
-                    Exception {
-                        address: address << 1,
-                        branch: branch != 0,
-                        privilege: privilege != 0,
-                        ecause: ecause as u8,
-                        interrupt: interrupt != 0,
-                        tvalepc,
-                    },
-                ))
-            }
-            if subformat == 3 {
-                let enable = reader.get_bits(1);
-                let qual_status = reader.get_bits(2);
-                let _sign_extend = reader.get_bits(1);
-
-                res.push(Packet::Support(
-                    index,
-                    Support {
-                        enable: enable != 0,
-                        qual_status: qual_status as u8,
-                    },
-                ))
-            }
+            ));
+        }
+    } else if format == 0b10 {
+        // format 2
+
+        let address = address(reader, differential)?;
+        let notify = reader.flag("notify")?;
+        let updiscon = reader.flag("updiscon")?;
+        reader.sign_extension()?;
+
+        packet = Some(Packet::Address(
+            index,
+            Address {
+                address,
+                notify,
+                updiscon,
+            },
+        ));
+    } else if format == 0b11 {
+        // format 3
+
+        let subformat = reader.field("subformat", 2)?;
+
+        if subformat == 0 {
+            let branch = reader.field("branch", 1)?;
+            let privilege = reader.field("privilege", 1)?;
+            let address = address(reader, false)?;
+            reader.sign_extension()?;
+
+            packet = Some(Packet::Sync(
+                index,
+                Sync {
+                    address,
+                    branch: branch != 0,
+                    privilege: privilege != 0,
+                },
+            ));
+        } else if subformat == 1 {
+            let branch = reader.field("branch", 1)?;
+            let privilege = reader.field("privilege", 1)?;
+            let ecause = reader.field("ecause", 5)?;
+            let interrupt = reader.field("interrupt", 1)?;
+            let address = address(reader, false)?;
+            let tvalepc = reader.field("tvalepc", 32)?;
+            reader.sign_extension()?;
+
+            packet = Some(Packet::Exception(
+                index,
+                Exception {
+                    address,
+                    branch: branch != 0,
+                    privilege: privilege != 0,
+                    ecause: ecause as u8,
+                    interrupt: interrupt != 0,
+                    tvalepc,
+                },
+            ));
         }
+        if subformat == 3 {
+            let enable = reader.field("enable", 1)?;
+            let qual_status = reader.field("qual_status", 2)?;
+            reader.sign_extension()?;
 
-        reader.skip_until(until);
+            packet = Some(Packet::Support(
+                index,
+                Support {
+                    enable: enable != 0,
+                    qual_status: qual_status as u8,
+                },
+            ));
+        }
     }
 
-    Ok(res)
+    Ok(packet)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Sync {
     pub branch: bool,
     pub privilege: bool,
     pub address: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Exception {
     pub branch: bool,
     pub privilege: bool,
@@ -179,20 +417,32 @@ pub struct Exception {
     pub tvalepc: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Support {
     pub enable: bool,
+    /// Why the filter qualification changed: 0 no change, 1 and 3 qualification ended, 2 trace
+    /// packets got lost
     pub qual_status: u8,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Support {
+    /// The encoder dropped one or more packets, e.g. because its FIFO overflowed
+    pub fn is_trace_lost(&self) -> bool {
+        self.qual_status == 0b10
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Address {
     pub address: u32,
+    /// A trigger requested the address, it's not the target of an uninferable jump
     pub notify: bool,
+    /// The address is the target of an uninferable jump and the instruction there is the last
+    /// one before the following format 3 packet (exception, privilege change or resync)
     pub updiscon: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct AddressBranchMap {
     pub address: u32,
     pub branches: u8,
@@ -201,13 +451,35 @@ pub struct AddressBranchMap {
     pub updiscon: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct NoAddressBranchMap {
     pub branches: u8,
     pub branch_map: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Format 0 packet reporting the number of correctly predicted branches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BranchCount {
+    /// Number of correctly predicted branches minus 31
+    pub branch_count: u32,
+    /// `None` if the branch following the correctly predicted ones was mispredicted
+    pub address: Option<u32>,
+    pub notify: bool,
+    pub updiscon: bool,
+}
+
+/// Format 0 packet reporting the target of an uninferable jump as index into the jump target
+/// cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JumpTargetIndex {
+    pub cache_index: u32,
+    pub branches: u8,
+    pub branch_map: u32,
+    pub notify: bool,
+    pub updiscon: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Packet {
     Sync(u32, Sync),
     Exception(u32, Exception),
@@ -215,64 +487,298 @@ pub enum Packet {
     Address(u32, Address),
     AddressBranchMap(u32, AddressBranchMap),
     NoAddressBranchMap(u32, NoAddressBranchMap),
+    BranchCount(u32, BranchCount),
+    JumpTargetIndex(u32, JumpTargetIndex),
 }
 
-struct Reader<'a> {
+impl Packet {
+    /// The 16 bit packet index
+    pub fn index(&self) -> u32 {
+        match self {
+            Packet::Sync(index, _)
+            | Packet::Exception(index, _)
+            | Packet::Support(index, _)
+            | Packet::Address(index, _)
+            | Packet::AddressBranchMap(index, _)
+            | Packet::NoAddressBranchMap(index, _)
+            | Packet::BranchCount(index, _)
+            | Packet::JumpTargetIndex(index, _) => *index,
+        }
+    }
+}
+
+/// Reads little endian bit fields, the first field starts at bit 0 of the first byte
+pub struct Reader<'a> {
     data: &'a [u8],
-    index: usize,
-    current_bit: u8,
-    bit_count: usize,
+    /// Byte offset of the data in the trace, reported in errors
+    offset: usize,
+    /// Number of bits read so far
+    position: usize,
+    /// The fields read so far, if they are recorded
+    fields: Option<Vec<Field>>,
 }
 
 impl<'a> Reader<'a> {
-    pub fn new(data: &'a [u8]) -> Self {
+    pub fn new(data: &'a [u8], offset: usize) -> Self {
         Self {
             data,
-            index: 0,
-            current_bit: 0,
-            bit_count: 0,
+            offset,
+            position: 0,
+            fields: None,
         }
     }
 
-    pub fn next_bit(&mut self) -> u32 {
-        let out = if self.data[self.index] & (1 << self.current_bit as u32) != 0 {
-            1
-        } else {
-            0
-        };
-        if self.current_bit < 7 {
-            self.current_bit += 1
-        } else {
-            self.index += 1;
-            self.current_bit = 0;
-        };
-        self.bit_count += 1;
-        out
+    /// Read the next field, it's recorded if [Reader::fields] is set
+    pub fn field(&mut self, name: &'static str, bits: usize) -> Result<u32, super::Error> {
+        let offset = self.position;
+        let value = self.get_bits(bits)?;
+
+        if let Some(fields) = self.fields.as_mut().filter(|_| bits != 0) {
+            fields.push(Field {
+                name,
+                offset,
+                width: bits,
+                value,
+                consistent: None,
+                set: None,
+            });
+        }
+        Ok(value)
+    }
+
+    /// Read a 1 bit flag, it's set when the bit differs from the bit in front of it
+    ///
+    /// This way a flag which isn't set is part of the sign extension of the field in front.
+    pub fn flag(&mut self, name: &'static str) -> Result<bool, super::Error> {
+        let last = self.position - 1;
+        let previous = (self.data[last / 8] >> (last % 8)) & 1;
+        Ok(self.field(name, 1)? != previous as u32)
     }
 
-    pub fn get_bits(&mut self, bits: usize) -> u32 {
-        let mut res = 0;
-        for i in 0..bits {
-            res |= self.next_bit() << i;
+    /// Read the rest of the packet, the sign extension of the last field
+    pub fn sign_extension(&mut self) -> Result<(), super::Error> {
+        let mut rest = self.data.len() * 8 - self.position;
+        while rest != 0 {
+            self.field("sign_extend", rest.min(32))?;
+            rest -= rest.min(32);
         }
-        res
+
+        Ok(())
     }
 
-    pub fn current_bit_count(&self) -> usize {
-        self.bit_count
+    /// Read the next `bits` bits (up to 32), fails when reading past the end of the data
+    pub fn get_bits(&mut self, bits: usize) -> Result<u32, super::Error> {
+        assert!(bits <= 32);
+        if self.position + bits > self.data.len() * 8 {
+            return Err(super::Error::TruncatedPacket {
+                offset: self.offset,
+                needed: (self.position + bits).div_ceil(8),
+            });
+        }
+
+        // a field of up to 32 bits at any bit offset spans at most 5 bytes
+        let first = self.position / 8;
+        let word = match self.data.get(first..first + 8) {
+            Some(word) => u64::from_le_bytes(word.try_into().unwrap()),
+            None => {
+                let last = (self.position + bits).div_ceil(8);
+                let mut word = 0u64;
+                for (i, byte) in self.data[first..last].iter().enumerate() {
+                    word |= (*byte as u64) << (i * 8);
+                }
+                word
+            }
+        };
+
+        let value = (word >> (self.position % 8)) & ((1u64 << bits) - 1);
+        self.position += bits;
+        Ok(value as u32)
+    }
+}
+
+#[test]
+fn test_parse_wrapped_buffer() {
+    let (_, trace) = crate::decoder::test_trace();
+
+    // the tail of an overwritten packet in front of the oldest complete packet
+    let mut data = vec![0x12, 0x04, 0x34, 0x00, 0x05];
+    data.extend_from_slice(&trace);
+
+    let mut parser = PacketParser::new();
+    parser.push(&data);
+    parser.finish();
+
+    let mut packets = Vec::new();
+    while let Some(packet) = parser.next_packet().unwrap() {
+        packets.push(packet);
     }
 
-    pub fn skip_until(&mut self, until: usize) {
-        while self.bit_count < until {
-            self.next_bit();
+    assert_eq!(parser.discarded(), 5);
+    assert_eq!(
+        packets.iter().map(Packet::index).collect::<Vec<_>>(),
+        [0, 1, 2, 3]
+    );
+    assert!(matches!(packets[0], Packet::Sync(_, sync) if sync.address == 0x100));
+}
+
+#[test]
+fn test_parse_wrapped_buffer_in_chunks() {
+    let (_, trace) = crate::decoder::test_trace();
+    let mut data = vec![0x08, 0xff, 0xff];
+    data.extend_from_slice(&trace);
+
+    let mut parser = PacketParser::new();
+    let mut packets = Vec::new();
+    for chunk in data.chunks(3) {
+        parser.push(chunk);
+        while let Some(packet) = parser.next_packet().unwrap() {
+            packets.push(packet);
         }
     }
+    parser.finish();
+    while let Some(packet) = parser.next_packet().unwrap() {
+        packets.push(packet);
+    }
+
+    assert_eq!(parser.discarded(), 3);
+    assert_eq!(packets.len(), 4);
+}
+
+#[test]
+fn test_reader() {
+    let data = [0b1010_1100, 0x34, 0x12, 0xff, 0x01];
+    let mut reader = Reader::new(&data, 100);
+
+    assert_eq!(reader.get_bits(2).unwrap(), 0b00);
+    assert_eq!(reader.get_bits(3).unwrap(), 0b011);
+    assert_eq!(reader.get_bits(0).unwrap(), 0);
+    assert_eq!(reader.get_bits(19).unwrap(), 0x1234 << 3 | 0b101);
+    assert_eq!(reader.get_bits(9).unwrap(), 0x1ff);
+    assert_eq!(reader.get_bits(7).unwrap(), 0);
+    assert!(matches!(
+        reader.get_bits(1),
+        Err(super::Error::TruncatedPacket {
+            offset: 100,
+            needed: 6
+        })
+    ));
+
+    let data = [0x78, 0x56, 0x34, 0x12, 0xab];
+    let mut reader = Reader::new(&data, 0);
+    assert_eq!(reader.get_bits(4).unwrap(), 0x8);
+    assert_eq!(reader.get_bits(32).unwrap(), 0xb123_4567);
+}
 
-    pub fn has_data(&self, count: usize) -> bool {
-        self.bit_count + count <= self.total_bit_count()
+#[test]
+fn test_parse_truncated_packet() {
+    let (_, trace) = crate::decoder::test_trace();
+
+    // an exception packet which claims to be only 4 bytes long
+    let mut exception = crate::decoder::test_packet(&[
+        (1, 16),
+        (0b11, 2),
+        (0b01, 2),
+        (0, 1),
+        (0, 1),
+        (2, 5),
+        (0, 1),
+        (0x200 >> 1, 31),
+        (0x104, 32),
+        (0, 6),
+    ]);
+    exception.truncate(4);
+    exception[0] = 4;
+
+    let mut data = trace[..8].to_vec();
+    data.extend(exception);
+    data.extend_from_slice(&trace[17..]);
+
+    assert!(matches!(
+        parse(&data, &DecoderConfig::default()),
+        Err(super::Error::TruncatedPacket {
+            offset: 8,
+            needed: 5
+        })
+    ));
+
+    // the offset counts from the start of the trace, not of the current chunk
+    let mut parser = PacketParser::new();
+    let mut error = None;
+    for chunk in data.chunks(3) {
+        parser.push(chunk);
+        while error.is_none() {
+            match parser.next_packet() {
+                Ok(Some(_)) => (),
+                Ok(None) => break,
+                Err(err) => error = Some(err),
+            }
+        }
     }
+    assert!(matches!(
+        error,
+        Some(super::Error::TruncatedPacket { offset: 8, .. })
+    ));
+}
 
-    pub fn total_bit_count(&self) -> usize {
-        self.data.len() * 8
+#[test]
+fn test_parse_truncated_last_packet() {
+    let (_, trace) = crate::decoder::test_trace();
+
+    // the support packet at offset 25 is missing its last byte
+    let data = &trace[..trace.len() - 1];
+    assert!(matches!(
+        parse(data, &DecoderConfig::default()),
+        Err(super::Error::TruncatedPacket {
+            offset: 25,
+            needed: 4
+        })
+    ));
+
+    // more data may still arrive until the parser is finished
+    let mut parser = PacketParser::new();
+    parser.push(data);
+    for _ in 0..3 {
+        assert!(parser.next_packet().unwrap().is_some());
     }
+    assert!(parser.next_packet().unwrap().is_none());
+    parser.finish();
+    assert!(matches!(
+        parser.next_packet(),
+        Err(super::Error::TruncatedPacket { offset: 25, .. })
+    ));
+
+    // a single packet which claims to be longer than the trace
+    assert!(matches!(
+        parse(&[0x0a, 0x00, 0x00, 0x03], &DecoderConfig::default()),
+        Err(super::Error::TruncatedPacket {
+            offset: 0,
+            needed: 10
+        })
+    ));
+}
+
+#[test]
+fn test_parse_notify_updiscon() {
+    // the flags are set when they differ from the bit in front of them, the address MSB for
+    // notify and notify for updiscon
+    let flags = |address: u32, notify: u32, updiscon: u32| {
+        let data = crate::decoder::test_packet(&[
+            (0, 16),
+            (0b10, 2),
+            (address >> 1, 31),
+            (notify, 1),
+            (updiscon, 1),
+            (updiscon * 0x1f, 5),
+        ]);
+        match crate::parse_packets(&data, &DecoderConfig::default()).unwrap()[..] {
+            [Packet::Address(_, address)] => (address.notify, address.updiscon),
+            ref packets => panic!("unexpected packets {:?}", packets),
+        }
+    };
+
+    assert_eq!(flags(0x4000_0000, 0, 0), (false, false));
+    assert_eq!(flags(0x4000_0000, 1, 0), (true, true));
+    assert_eq!(flags(0x4000_0000, 0, 1), (false, true));
+    assert_eq!(flags(0x8000_0000, 1, 1), (false, false));
+    assert_eq!(flags(0x8000_0000, 0, 0), (true, false));
 }
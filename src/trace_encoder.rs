@@ -0,0 +1,606 @@
+use crate::config::{AddressMode, DecoderConfig};
+use crate::trace_decoder::Packet;
+use crate::Error;
+
+/// Serialize packets into trace data, the inverse of [crate::parse_packets]
+pub fn encode(packets: &[Packet], config: &DecoderConfig) -> Result<Vec<u8>, Error> {
+    let mut encoder = PacketEncoder::with_config(config.clone())?;
+    let mut res = Vec::new();
+    for packet in packets {
+        res.extend(encoder.encode(packet)?);
+    }
+
+    Ok(res)
+}
+
+/// Serializes packets one by one, the inverse of [crate::PacketParser]
+///
+/// Sign extension bits repeat the last bit of the field in front of them up to the end of the
+/// packet. Values which the parser can't produce, e.g. odd addresses or a branch map with bits
+/// beyond its width, are rejected instead of being truncated silently.
+pub struct PacketEncoder {
+    config: DecoderConfig,
+    /// The last reported address, differential addresses are relative to it
+    last_address: u32,
+}
+
+impl PacketEncoder {
+    /// Create an encoder for the default encoder configuration
+    pub fn new() -> Self {
+        Self::with_config(DecoderConfig::default()).unwrap()
+    }
+
+    /// Create an encoder for the given configuration
+    pub fn with_config(config: DecoderConfig) -> Result<Self, Error> {
+        config.validate()?;
+
+        Ok(Self {
+            config,
+            last_address: 0,
+        })
+    }
+
+    /// Serialize the next packet including its header
+    pub fn encode(&mut self, packet: &Packet) -> Result<Vec<u8>, Error> {
+        let config = &self.config;
+        let index = packet.index();
+        let check = |valid: bool, reason: &'static str| match valid {
+            true => Ok(()),
+            false => Err(Error::UnencodablePacket { index, reason }),
+        };
+        let branch_map = |branches: u32, branch_map: u32| {
+            let bits = config
+                .branch_map_bits(branches)
+                .ok_or(Error::UnencodablePacket {
+                    index,
+                    reason: "the branches don't fit into a branch map",
+                })?;
+            check(
+                branch_map >> bits == 0,
+                "the branch map has more bits than branches",
+            )?;
+            Ok::<usize, Error>(bits)
+        };
+        let differential = config.address_mode == AddressMode::Differential;
+        let mut last_address = self.last_address;
+        let mut address = |address: u32, differential: bool| {
+            check(
+                config.is_reportable(address),
+                "the address has bits which aren't reported",
+            )?;
+            let field = config.address_field(address, last_address, differential);
+            last_address = address;
+            Ok::<u32, Error>(field)
+        };
+
+        check(index <= 0xffff, "the index doesn't fit into 16 bits")?;
+
+        let mut writer = Writer::new();
+        // the length is filled in at the end
+        writer.put_bits(0, 5);
+        writer.put_bits(0, 3);
+        writer.put_bits(index, 16);
+
+        match packet {
+            Packet::BranchCount(_, packet) => {
+                check(config.format0_extensions, "format 0 packets aren't enabled")?;
+
+                writer.put_bits(0b00, 2);
+                writer.put_bits(0, 1);
+                writer.put_bits(packet.branch_count, 32);
+                match packet.address {
+                    Some(reported) => {
+                        writer.put_bits(0b10, 2);
+                        writer.put_bits(address(reported, differential)?, config.address_bits());
+                        writer.put_flag(packet.notify);
+                        writer.put_flag(packet.updiscon);
+                    }
+                    None => {
+                        check(
+                            !packet.notify && !packet.updiscon,
+                            "notify and updiscon are only reported with an address",
+                        )?;
+                        writer.put_bits(0b00, 2);
+                    }
+                }
+            }
+            Packet::JumpTargetIndex(_, packet) => {
+                check(config.format0_extensions, "format 0 packets aren't enabled")?;
+                check(
+                    packet.cache_index >> config.jump_target_cache_size == 0,
+                    "the jump target index doesn't fit the cache",
+                )?;
+                let bits = match packet.branches {
+                    0 => {
+                        check(
+                            packet.branch_map == 0,
+                            "the branch map has more bits than branches",
+                        )?;
+                        0
+                    }
+                    branches => branch_map(branches as u32, packet.branch_map)?,
+                };
+
+                writer.put_bits(0b00, 2);
+                writer.put_bits(1, 1);
+                writer.put_bits(packet.cache_index, config.jump_target_cache_size as usize);
+                writer.put_bits(packet.branches as u32, 5);
+                writer.put_bits(packet.branch_map, bits);
+                writer.put_flag(packet.notify);
+                writer.put_flag(packet.updiscon);
+            }
+            Packet::AddressBranchMap(_, packet) => {
+                check(packet.branches != 0, "the number of branches is 0")?;
+                let bits = branch_map(packet.branches as u32, packet.branch_map)?;
+
+                writer.put_bits(0b01, 2);
+                writer.put_bits(packet.branches as u32, 5);
+                writer.put_bits(packet.branch_map, bits);
+                writer.put_bits(
+                    address(packet.address, differential)?,
+                    config.address_bits(),
+                );
+                writer.put_flag(packet.notify);
+                writer.put_flag(packet.updiscon);
+            }
+            Packet::NoAddressBranchMap(_, packet) => {
+                // the parser reports a full map as 0 branches
+                check(packet.branches == 0, "the number of branches isn't 0")?;
+                let bits = branch_map(config.max_branches(), packet.branch_map)?;
+
+                writer.put_bits(0b01, 2);
+                writer.put_bits(0, 5);
+                writer.put_bits(packet.branch_map, bits);
+            }
+            Packet::Address(_, packet) => {
+                writer.put_bits(0b10, 2);
+                writer.put_bits(
+                    address(packet.address, differential)?,
+                    config.address_bits(),
+                );
+                writer.put_flag(packet.notify);
+                writer.put_flag(packet.updiscon);
+            }
+            Packet::Sync(_, packet) => {
+                writer.put_bits(0b11, 2);
+                writer.put_bits(0, 2);
+                writer.put_bits(packet.branch as u32, 1);
+                writer.put_bits(packet.privilege as u32, 1);
+                writer.put_bits(address(packet.address, false)?, config.address_bits());
+            }
+            Packet::Exception(_, packet) => {
+                check(
+                    packet.ecause < 32,
+                    "the exception cause doesn't fit into 5 bits",
+                )?;
+
+                writer.put_bits(0b11, 2);
+                writer.put_bits(1, 2);
+                writer.put_bits(packet.branch as u32, 1);
+                writer.put_bits(packet.privilege as u32, 1);
+                writer.put_bits(packet.ecause as u32, 5);
+                writer.put_bits(packet.interrupt as u32, 1);
+                writer.put_bits(address(packet.address, false)?, config.address_bits());
+                writer.put_bits(packet.tvalepc, 32);
+            }
+            Packet::Support(_, packet) => {
+                check(
+                    packet.qual_status < 4,
+                    "the qualification status doesn't fit into 2 bits",
+                )?;
+
+                writer.put_bits(0b11, 2);
+                writer.put_bits(3, 2);
+                writer.put_bits(packet.enable as u32, 1);
+                writer.put_bits(packet.qual_status as u32, 2);
+            }
+        }
+        writer.sign_extend();
+        check(writer.data.len() < 32, "the packet is longer than 31 bytes")?;
+
+        self.last_address = last_address;
+        let mut data = writer.data;
+        data[0] = data.len() as u8;
+        Ok(data)
+    }
+}
+
+impl Default for PacketEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes little endian bit fields, the counterpart of the packet reader
+struct Writer {
+    data: Vec<u8>,
+    /// Number of bits written so far
+    position: usize,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Append the low `bits` bits (up to 32) of `value`
+    fn put_bits(&mut self, value: u32, bits: usize) {
+        for i in 0..bits {
+            self.put_bit((value >> i) & 1 != 0);
+        }
+    }
+
+    /// Write a flag which is set when it differs from the bit in front of it
+    fn put_flag(&mut self, flag: bool) {
+        self.put_bit(flag != self.last_bit());
+    }
+
+    /// Repeat the last bit written up to the end of the current byte
+    fn sign_extend(&mut self) {
+        let bit = self.last_bit();
+        while self.position != self.data.len() * 8 {
+            self.put_bit(bit);
+        }
+    }
+
+    fn last_bit(&self) -> bool {
+        let last = self.position - 1;
+        self.data[last / 8] & (1 << (last % 8)) != 0
+    }
+
+    fn put_bit(&mut self, bit: bool) {
+        if self.position == self.data.len() * 8 {
+            self.data.push(0);
+        }
+        if bit {
+            self.data[self.position / 8] |= 1 << (self.position % 8);
+        }
+        self.position += 1;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod strategies {
+    use crate::config::{AddressMode, DecoderConfig};
+    use crate::trace_decoder::*;
+    use proptest::prelude::*;
+
+    pub(crate) fn config() -> impl Strategy<Value = DecoderConfig> {
+        (
+            prop_oneof![Just(AddressMode::Full), Just(AddressMode::Differential)],
+            0..3u32,
+            24..=32u32,
+            prop_oneof![
+                Just(vec![1, 3, 7, 15, 31]),
+                Just(vec![1, 3, 7, 15]),
+                Just(vec![31]),
+                Just(vec![2, 8, 16]),
+            ],
+            any::<bool>(),
+            0..8u32,
+        )
+            .prop_map(
+                |(
+                    address_mode,
+                    iaddress_lsb,
+                    iaddress_width,
+                    branch_map_widths,
+                    format0_extensions,
+                    jump_target_cache_size,
+                )| DecoderConfig {
+                    address_mode,
+                    iaddress_lsb,
+                    iaddress_width,
+                    branch_map_widths,
+                    format0_extensions,
+                    jump_target_cache_size,
+                },
+            )
+    }
+
+    fn address(config: &DecoderConfig) -> impl Strategy<Value = u32> {
+        let mask = (u32::MAX >> (32 - config.iaddress_width)) & !((1 << config.iaddress_lsb) - 1);
+        any::<u32>().prop_map(move |address| address & mask)
+    }
+
+    /// A branch count and a branch map of the matching width
+    fn branches(config: &DecoderConfig, min: u32) -> impl Strategy<Value = (u8, u32)> {
+        let config = config.clone();
+        (min..=config.max_branches(), any::<u32>()).prop_map(move |(branches, bits)| {
+            let width = match branches {
+                0 => 0,
+                _ => config.branch_map_bits(branches).unwrap(),
+            };
+            (branches as u8, (bits as u64 & ((1 << width) - 1)) as u32)
+        })
+    }
+
+    pub(super) fn packet(index: u32, config: &DecoderConfig) -> BoxedStrategy<Packet> {
+        let max_branches = config.max_branches();
+        let mut packets = vec![
+            (any::<bool>(), any::<bool>(), address(config))
+                .prop_map(move |(branch, privilege, address)| {
+                    Packet::Sync(
+                        index,
+                        Sync {
+                            branch,
+                            privilege,
+                            address,
+                        },
+                    )
+                })
+                .boxed(),
+            (
+                any::<bool>(),
+                any::<bool>(),
+                0..32u8,
+                any::<bool>(),
+                address(config),
+                any::<u32>(),
+            )
+                .prop_map(
+                    move |(branch, privilege, ecause, interrupt, address, tvalepc)| {
+                        Packet::Exception(
+                            index,
+                            Exception {
+                                branch,
+                                privilege,
+                                ecause,
+                                interrupt,
+                                address,
+                                tvalepc,
+                            },
+                        )
+                    },
+                )
+                .boxed(),
+            (any::<bool>(), 0..4u8)
+                .prop_map(move |(enable, qual_status)| {
+                    Packet::Support(
+                        index,
+                        Support {
+                            enable,
+                            qual_status,
+                        },
+                    )
+                })
+                .boxed(),
+            (address(config), any::<bool>(), any::<bool>())
+                .prop_map(move |(address, notify, updiscon)| {
+                    Packet::Address(
+                        index,
+                        Address {
+                            address,
+                            notify,
+                            updiscon,
+                        },
+                    )
+                })
+                .boxed(),
+            (
+                branches(config, 1),
+                address(config),
+                any::<bool>(),
+                any::<bool>(),
+            )
+                .prop_map(move |((branches, branch_map), address, notify, updiscon)| {
+                    Packet::AddressBranchMap(
+                        index,
+                        AddressBranchMap {
+                            address,
+                            branches,
+                            branch_map,
+                            notify,
+                            updiscon,
+                        },
+                    )
+                })
+                .boxed(),
+            any::<u32>()
+                .prop_map(move |bits| {
+                    Packet::NoAddressBranchMap(
+                        index,
+                        NoAddressBranchMap {
+                            branches: 0,
+                            branch_map: (bits as u64 & ((1 << max_branches) - 1)) as u32,
+                        },
+                    )
+                })
+                .boxed(),
+        ];
+
+        if config.format0_extensions {
+            let cache_size = config.jump_target_cache_size;
+            packets.push(
+                (
+                    any::<u32>(),
+                    proptest::option::of(address(config)),
+                    any::<bool>(),
+                    any::<bool>(),
+                )
+                    .prop_map(move |(branch_count, address, notify, updiscon)| {
+                        Packet::BranchCount(
+                            index,
+                            BranchCount {
+                                branch_count,
+                                address,
+                                notify: notify && address.is_some(),
+                                updiscon: updiscon && address.is_some(),
+                            },
+                        )
+                    })
+                    .boxed(),
+            );
+            packets.push(
+                (
+                    any::<u32>(),
+                    branches(config, 0),
+                    any::<bool>(),
+                    any::<bool>(),
+                )
+                    .prop_map(
+                        move |(cache_index, (branches, branch_map), notify, updiscon)| {
+                            Packet::JumpTargetIndex(
+                                index,
+                                JumpTargetIndex {
+                                    cache_index: cache_index & ((1 << cache_size) - 1),
+                                    branches,
+                                    branch_map,
+                                    notify,
+                                    updiscon,
+                                },
+                            )
+                        },
+                    )
+                    .boxed(),
+            );
+        }
+
+        proptest::strategy::Union::new(packets).boxed()
+    }
+
+    /// Packets with consecutive indices, the index wraps around like on hardware
+    pub(super) fn packets(config: &DecoderConfig) -> impl Strategy<Value = Vec<Packet>> {
+        let config = config.clone();
+        (0..=0xffffu32, 1..20usize).prop_flat_map(move |(first, len)| {
+            (0..len as u32)
+                .map(|i| packet((first + i) & 0xffff, &config))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    pub(super) fn config_and_packets() -> impl Strategy<Value = (DecoderConfig, Vec<Packet>)> {
+        config().prop_flat_map(|config| (Just(config.clone()), packets(&config)))
+    }
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_round_trip_packet(packet in strategies::packet(0x1234, &DecoderConfig::default())) {
+        let config = DecoderConfig::default();
+        let data = PacketEncoder::new().encode(&packet).unwrap();
+        proptest::prop_assert_eq!(data[0] as usize, data.len());
+        proptest::prop_assert_eq!(crate::parse_packets(&data, &config).unwrap(), vec![packet]);
+
+        // every sign extension bit repeats the bit in front of it
+        let dump = crate::dump_packets(&data, &config).unwrap();
+        proptest::prop_assert!(dump[0]
+            .fields
+            .iter()
+            .all(|field| field.consistent != Some(false)));
+    }
+
+    #[test]
+    fn test_round_trip_trace((config, packets) in strategies::config_and_packets()) {
+        let data = encode(&packets, &config).unwrap();
+        let parsed = crate::parse_packets(&data, &config).unwrap();
+        proptest::prop_assert_eq!(&parsed, &packets);
+
+        // parsing and encoding again gives the same bytes
+        proptest::prop_assert_eq!(encode(&parsed, &config).unwrap(), data);
+    }
+}
+
+#[test]
+fn test_encode_matches_hand_made_packets() {
+    let (_, trace) = crate::decoder::test_trace();
+    let config = DecoderConfig::default();
+    let packets = crate::parse_packets(&trace, &config).unwrap();
+    assert_eq!(encode(&packets, &config).unwrap(), trace);
+}
+
+#[test]
+fn test_encode_differential_addresses() {
+    use crate::trace_decoder::{Address, Sync};
+
+    let config = DecoderConfig {
+        address_mode: AddressMode::Differential,
+        ..Default::default()
+    };
+    let packets = [
+        Packet::Sync(
+            0,
+            Sync {
+                branch: true,
+                privilege: false,
+                address: 0x4200_0100,
+            },
+        ),
+        Packet::Address(
+            1,
+            Address {
+                address: 0x4200_00f0,
+                notify: false,
+                updiscon: false,
+            },
+        ),
+    ];
+    let data = encode(&packets, &config).unwrap();
+
+    // the second address is -0x10 relative to the first one, halved
+    let address = crate::dump_packets(&data, &config).unwrap()[1]
+        .field("address")
+        .unwrap();
+    assert_eq!(address, 0x7fff_fff8);
+    // without the configuration it's taken as full address
+    assert!(matches!(
+        crate::parse_packets(&data, &DecoderConfig::default()).unwrap()[1],
+        Packet::Address(
+            _,
+            Address {
+                address: 0xffff_fff0,
+                ..
+            }
+        )
+    ));
+}
+
+#[test]
+fn test_encode_invalid_packets() {
+    use crate::trace_decoder::{Address, BranchCount, NoAddressBranchMap};
+
+    let mut encoder = PacketEncoder::new();
+    let packet = Packet::Address(
+        3,
+        Address {
+            address: 0x101,
+            notify: false,
+            updiscon: false,
+        },
+    );
+    assert!(matches!(
+        encoder.encode(&packet),
+        Err(Error::UnencodablePacket { index: 3, .. })
+    ));
+
+    let packet = Packet::NoAddressBranchMap(
+        0x10000,
+        NoAddressBranchMap {
+            branches: 0,
+            branch_map: 0,
+        },
+    );
+    assert!(matches!(
+        encoder.encode(&packet),
+        Err(Error::UnencodablePacket { index: 0x10000, .. })
+    ));
+
+    // format 0 isn't enabled
+    let packet = Packet::BranchCount(
+        4,
+        BranchCount {
+            branch_count: 0,
+            address: None,
+            notify: false,
+            updiscon: false,
+        },
+    );
+    assert!(matches!(
+        encoder.encode(&packet),
+        Err(Error::UnencodablePacket { index: 4, .. })
+    ));
+}
@@ -0,0 +1,77 @@
+/// An exception or interrupt reported by the trace encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub cause: u8,
+    pub interrupt: bool,
+    /// The trapping instruction for exceptions, the interrupted instruction for interrupts
+    pub epc: u32,
+    /// The first instruction of the trap handler
+    pub handler: u32,
+}
+
+impl Trap {
+    /// Human readable name of the cause
+    ///
+    /// ESP32-C6/H2 interrupt causes are the number of the CPU interrupt line, only the core local
+    /// interrupts have a name.
+    pub fn cause_name(&self) -> String {
+        if self.interrupt {
+            match self.cause {
+                3 => "Machine software interrupt".to_string(),
+                7 => "Machine timer interrupt".to_string(),
+                cause => format!("CPU interrupt {}", cause),
+            }
+        } else {
+            match self.cause {
+                0 => "Instruction address misaligned".to_string(),
+                1 => "Instruction access fault".to_string(),
+                2 => "Illegal instruction".to_string(),
+                3 => "Breakpoint".to_string(),
+                4 => "Load address misaligned".to_string(),
+                5 => "Load access fault".to_string(),
+                6 => "Store/AMO address misaligned".to_string(),
+                7 => "Store/AMO access fault".to_string(),
+                8 => "Environment call from U-mode".to_string(),
+                9 => "Environment call from S-mode".to_string(),
+                11 => "Environment call from M-mode".to_string(),
+                12 => "Instruction page fault".to_string(),
+                13 => "Load page fault".to_string(),
+                15 => "Store/AMO page fault".to_string(),
+                cause => format!("Reserved exception {}", cause),
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {:#010x}, handler {:#010x}",
+            self.cause_name(),
+            self.epc,
+            self.handler
+        )
+    }
+}
+
+#[test]
+fn test_cause_names() {
+    let mut trap = Trap {
+        cause: 7,
+        interrupt: true,
+        epc: 0x42000010,
+        handler: 0x40800000,
+    };
+    assert_eq!(
+        trap.to_string(),
+        "Machine timer interrupt at 0x42000010, handler 0x40800000"
+    );
+
+    trap.cause = 17;
+    assert_eq!(trap.cause_name(), "CPU interrupt 17");
+
+    trap.interrupt = false;
+    trap.cause = 11;
+    assert_eq!(trap.cause_name(), "Environment call from M-mode");
+}